@@ -0,0 +1,884 @@
+/// Build Module
+/// Renders a site's markdown posts into a fresh output directory.
+/// Source files in `md_posts/` are only ever read, never written.
+use crate::config::SiteConfig;
+use crate::feeds::{self, FeedSite};
+use crate::front_matter::{FrontMatter, PostState};
+use crate::sitemap::{self, SitemapEntry};
+use crate::tags::{self, Tag, TagLink};
+use crate::{slugify, SiteDetails};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tinytemplate::TinyTemplate;
+
+/// Name of the directory, inside the site directory, that holds the built site
+pub const OUTPUT_DIR: &str = "public";
+
+/// How dates are shown on the blog cards and post pages
+const DISPLAY_DATE_FORMAT: &str = "%Y/%m/%d %I:%M %p";
+
+static POST_CARD_TEMPLATE: &str = include_str!("templates/default/blog-card-template.html");
+static POST_PAGE_TEMPLATE: &str = include_str!("templates/default/post-template.html");
+static INDEX_TEMPLATE: &str = include_str!("templates/default/index-template.html");
+static TAGS_TEMPLATE: &str = include_str!("templates/default/tags-template.html");
+static SHARED_STYLES_TEMPLATE: &str = include_str!("templates/default/shared-styles.html");
+
+#[derive(Serialize)]
+struct BlogCardContext {
+    filename: String,
+    title: String,
+    date: String,
+    updated: String,
+    excerpt: String,
+    image: String,
+    sitename: String,
+    tags: String,
+    tag_links: Vec<TagLink>,
+    extra: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct IndexContext {
+    root: String,
+    title: String,
+    heading: String,
+    sitename: String,
+    blog_cards: String,
+    feeds: bool,
+    page: usize,
+    total_pages: usize,
+    prev_url: String,
+    next_url: String,
+}
+
+#[derive(Serialize)]
+struct TagsContext {
+    root: String,
+    sitename: String,
+    tags: Vec<TagCloudEntry>,
+}
+
+#[derive(Serialize)]
+struct TagCloudEntry {
+    name: String,
+    url: String,
+    count: usize,
+    size: String,
+}
+
+#[derive(Serialize)]
+struct PostContext {
+    root: String,
+    title: String,
+    content: String,
+    date: String,
+    updated: String,
+    excerpt: String,
+    image: String,
+    tags: String,
+    tag_links: Vec<TagLink>,
+    sitename: String,
+    extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// Name of the directory, inside the site directory, that holds local previews
+pub const PREVIEW_DIR: &str = "preview";
+
+/// BuildOptions struct
+/// Controls how a site is built
+/// output_dir: Where to write the built site, defaults to `<site>/public`
+/// include_drafts: Also build drafts and scheduled posts, for local previews
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    pub output_dir: Option<PathBuf>,
+    pub include_drafts: bool,
+}
+
+/// PostSummary struct
+/// A short description of a post, for listing a site's posts
+/// title: The title of the post
+/// slug: The output file name of the post, without extension
+/// date: The date the post was written, as shown on the page
+/// state: Whether the post is published, a draft or scheduled
+#[derive(Debug, Clone)]
+pub struct PostSummary {
+    pub title: String,
+    pub slug: String,
+    pub date: String,
+    pub state: PostState,
+}
+
+/// SourcePost struct
+/// A markdown post read from `md_posts/` and rendered to HTML in memory
+/// slug: The output file name of the post, without extension
+/// title: The title of the post
+/// date: The parsed post date, used for sorting
+/// state: Whether the post is published, a draft or scheduled
+/// front_matter: The post's metadata
+/// content: The rendered HTML body of the post
+pub(crate) struct SourcePost {
+    pub slug: String,
+    pub title: String,
+    pub date: Option<NaiveDateTime>,
+    pub state: PostState,
+    pub front_matter: FrontMatter,
+    pub content: String,
+}
+
+impl SourcePost {
+    /// Parse a markdown source into a post
+    /// A slug set in the front matter is cleaned like a title, so it can't point outside `posts/`
+    /// file_stem: The markdown file name without extension, the fallback slug and title
+    /// now: The time the post's state is worked out against
+    fn parse(file_stem: &str, source: &str, now: NaiveDateTime) -> Result<SourcePost> {
+        let (front_matter, body) = FrontMatter::parse(source)?;
+        let slug = match &front_matter.slug {
+            Some(slug) => {
+                let cleaned = slugify(slug)?;
+                if cleaned.is_empty() {
+                    bail!("The slug {:?} has no letters or digits", slug);
+                }
+                cleaned
+            }
+            None => file_stem.to_string(),
+        };
+        let title = front_matter
+            .title
+            .clone()
+            .unwrap_or_else(|| file_stem.replace('-', " "));
+
+        Ok(SourcePost {
+            slug,
+            title,
+            date: front_matter.parsed_date(),
+            state: front_matter.state(now),
+            content: render_markdown(&body),
+            front_matter,
+        })
+    }
+
+    /// When the post last changed, its updated date or else its date
+    pub fn lastmod(&self) -> Option<NaiveDateTime> {
+        self.front_matter.parsed_updated().or(self.date)
+    }
+
+    /// The post date as shown on the page
+    fn display_date(&self) -> String {
+        format_date(self.date, self.front_matter.date.as_deref())
+    }
+
+    /// The updated date as shown on the page, empty if the post was never updated
+    fn display_updated(&self) -> String {
+        format_date(
+            self.front_matter.parsed_updated(),
+            self.front_matter.updated.as_deref(),
+        )
+    }
+}
+
+/// Format a parsed date for display, falling back to the raw front matter value
+fn format_date(parsed: Option<NaiveDateTime>, raw: Option<&str>) -> String {
+    match parsed {
+        Some(date) => date.format(DISPLAY_DATE_FORMAT).to_string(),
+        None => raw.unwrap_or_default().to_string(),
+    }
+}
+
+impl BuildOptions {
+    /// Resolve the output directory for a site
+    pub fn output_path(&self, site: &SiteDetails) -> Result<PathBuf> {
+        match &self.output_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => Ok(site.build_site_path()?.join(OUTPUT_DIR)),
+        }
+    }
+}
+
+/// Build a site from its markdown sources
+/// Every file is rendered into a staging directory first, which only replaces
+/// the output directory once the whole build has succeeded.
+/// Returns the path of the output directory
+pub fn build_site(site: &SiteDetails, options: &BuildOptions) -> Result<PathBuf> {
+    let site_path = site.build_site_path()?;
+    let md_posts_path = site_path.join("md_posts");
+    let output_path = options.output_path(site)?;
+    // the site is built next to the output directory first, e.g. `.public.tmp`
+    let output_name = output_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid output directory: {}", output_path.display()))?;
+    let staging_path = output_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(format!(".{}.tmp", output_name));
+
+    println!(">> Building site: {}", site_path.display());
+
+    if !md_posts_path.exists() {
+        fs::create_dir_all(&md_posts_path)
+            .context("Failed to create this site's 'md_posts' directory")?;
+    }
+
+    // start from a clean staging directory, a previous failed build may have left one behind
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path).context("Failed to clear the staging directory")?;
+    }
+    fs::create_dir_all(staging_path.join("posts"))
+        .context("Failed to create the staging directory")?;
+
+    let mut posts = read_posts(&md_posts_path)?;
+    if !options.include_drafts {
+        posts.retain(|post| post.state == PostState::Published);
+    }
+    check_unique_slugs(&posts)?;
+    let config = SiteConfig::load(site)?;
+    if let Err(e) = write_site(site, &config, &posts, &staging_path) {
+        let _ = fs::remove_dir_all(&staging_path);
+        return Err(e);
+    }
+
+    // swap the finished build into place
+    if output_path.exists() {
+        fs::remove_dir_all(&output_path).context("Failed to remove the previous build")?;
+    }
+    fs::rename(&staging_path, &output_path).context("Failed to move the build into place")?;
+
+    println!(">> Site built: {}", output_path.display());
+    Ok(output_path)
+}
+
+/// List every post of a site with its publishing state
+/// Posts are returned sorted by date, newest first
+pub fn list_posts(site: &SiteDetails) -> Result<Vec<PostSummary>> {
+    let md_posts_path = site.build_site_path()?.join("md_posts");
+    if !md_posts_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let posts = read_posts(&md_posts_path)?
+        .into_iter()
+        .map(|post| PostSummary {
+            date: post.display_date(),
+            title: post.title,
+            slug: post.slug,
+            state: post.state,
+        })
+        .collect();
+    Ok(posts)
+}
+
+/// Fail if two posts would be written to the same page, one would silently replace the other
+fn check_unique_slugs(posts: &[SourcePost]) -> Result<()> {
+    let mut seen: BTreeMap<&str, &str> = BTreeMap::new();
+    for post in posts {
+        if let Some(other) = seen.insert(&post.slug, &post.title) {
+            bail!(
+                "The posts \"{}\" and \"{}\" both have the slug \"{}\", give one of them another slug",
+                other,
+                post.title,
+                post.slug
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Convert a markdown string to HTML
+pub fn render_markdown(md_input: &str) -> String {
+    let parser = pulldown_cmark::Parser::new(md_input);
+    let mut html_output = String::new();
+    pulldown_cmark::html::push_html(&mut html_output, parser);
+    html_output
+}
+
+/// Read every markdown file in `md_posts/` and render it to HTML in memory
+/// Posts are returned sorted by date, newest first
+fn read_posts(md_posts_path: &Path) -> Result<Vec<SourcePost>> {
+    let mut posts = Vec::new();
+    let now = chrono::Local::now().naive_local();
+
+    for entry in fs::read_dir(md_posts_path)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().and_then(|e| e.to_str()) != Some("md") {
+            continue;
+        }
+        println!(">> Reading post: {}", path.display());
+
+        let file_stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow!("Invalid post file name: {}", path.display()))?;
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        posts.push(
+            SourcePost::parse(file_stem, &source, now)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+        );
+    }
+
+    // Sort posts by date in descending order (newest first)
+    posts.sort_by_key(|post| std::cmp::Reverse(post.date));
+    Ok(posts)
+}
+
+/// Write every output file of the site into the given directory
+fn write_site(
+    site: &SiteDetails,
+    config: &SiteConfig,
+    posts: &[SourcePost],
+    out_path: &Path,
+) -> Result<()> {
+    let site_name = site.display_name();
+    let tags = tags::collect_tags(posts);
+    // feeds and the sitemap need absolute URLs, so they are only written when the site has one
+    let base_url = config.resolve_base_url(site);
+    let renderer = Renderer::new(&site_name, config.page_size, base_url.is_some())?;
+    render_site(&renderer, posts, &tags, out_path)?;
+
+    match base_url {
+        Some(base_url) => {
+            let feed_site = FeedSite {
+                title: &site_name,
+                base_url: &base_url,
+            };
+            feeds::write_feeds(&feed_site, posts, config.feed_limit, out_path)?;
+            let entries = sitemap_entries(posts, &tags, config.page_size);
+            sitemap::write_sitemap(&base_url, &entries, out_path)?;
+        }
+        None => println!(">> No base URL for this site, skipping feeds and sitemap"),
+    }
+
+    Ok(())
+}
+
+/// List every page of the site for the sitemap
+/// A page's lastmod is the newest date of the posts it shows
+fn sitemap_entries(posts: &[SourcePost], tags: &[Tag], page_size: usize) -> Vec<SitemapEntry> {
+    let all_posts = posts.iter().collect::<Vec<_>>();
+    let mut entries = listing_entries("/", &all_posts, page_size);
+
+    for post in posts {
+        entries.push(SitemapEntry {
+            path: post_url(&post.slug),
+            lastmod: post.lastmod(),
+        });
+    }
+
+    entries.push(SitemapEntry {
+        path: "/tags/".to_string(),
+        lastmod: entries[0].lastmod,
+    });
+    for tag in tags {
+        entries.extend(listing_entries(
+            &tags::tag_url(&tag.slug),
+            &tag.posts,
+            page_size,
+        ));
+    }
+
+    entries
+}
+
+/// List every page of a paginated listing for the sitemap
+fn listing_entries(url_path: &str, posts: &[&SourcePost], page_size: usize) -> Vec<SitemapEntry> {
+    paginate(posts, page_size)
+        .into_iter()
+        .enumerate()
+        .map(|(i, page_posts)| SitemapEntry {
+            path: page_url(url_path, i + 1),
+            lastmod: page_posts.iter().filter_map(|post| post.lastmod()).max(),
+        })
+        .collect()
+}
+
+/// Split a listing into pages of `page_size` posts, 0 keeps them all on one page
+/// There is always at least one page, so a site without posts still gets an index
+fn paginate<'a, 'b>(posts: &'a [&'b SourcePost], page_size: usize) -> Vec<&'a [&'b SourcePost]> {
+    if page_size == 0 || posts.is_empty() {
+        return vec![posts];
+    }
+    posts.chunks(page_size).collect()
+}
+
+/// The path of a post's page from the site root
+fn post_url(slug: &str) -> String {
+    format!("/posts/{}.html", slug)
+}
+
+/// The prefix that leads from a page back to the site root, e.g. `../` for `/posts/hello.html`
+/// Links are relative so the site works from any path, and when opened from disk
+/// url_path: The path of the page, a listing's path ends in `/`
+fn root_prefix(url_path: &str) -> String {
+    "../".repeat(url_path.trim_start_matches('/').matches('/').count())
+}
+
+/// A link to a page of the site, relative to the page it's on
+/// Directories link to their `index.html`, which a browser opening files from disk won't add
+/// root: The root_prefix of the page the link is on
+/// url_path: The path of the linked page from the site root, e.g. `/tags/rust/`
+pub(crate) fn relative_url(root: &str, url_path: &str) -> String {
+    let path = url_path.trim_start_matches('/');
+    if path.is_empty() || path.ends_with('/') {
+        format!("{}{}index.html", root, path)
+    } else {
+        format!("{}{}", root, path)
+    }
+}
+
+/// The path of a page of a listing, page 1 is the listing itself
+fn page_url(url_path: &str, page: usize) -> String {
+    if page <= 1 {
+        url_path.to_string()
+    } else {
+        format!("{}page/{}/", url_path, page)
+    }
+}
+
+/// Renderer struct
+/// Templates the pages of a site
+/// tt: The templates, all registered up front
+/// site_name: The human readable name of the site
+/// page_size: The number of posts per listing page, 0 for a single page
+/// feeds: Whether the site has feeds for the listings to link to
+struct Renderer<'a> {
+    tt: TinyTemplate<'a>,
+    site_name: &'a str,
+    page_size: usize,
+    feeds: bool,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(site_name: &'a str, page_size: usize, feeds: bool) -> Result<Renderer<'a>> {
+        // values are HTML-escaped, only the rendered markdown and cards are inserted as they are
+        let mut tt = TinyTemplate::new();
+        tt.add_template("card", POST_CARD_TEMPLATE)?;
+        tt.add_template("post", POST_PAGE_TEMPLATE)?;
+        tt.add_template("index", INDEX_TEMPLATE)?;
+        tt.add_template("tags", TAGS_TEMPLATE)?;
+        // the styles the index and tag pages have in common, called from both
+        tt.add_template("styles", SHARED_STYLES_TEMPLATE)?;
+        Ok(Renderer {
+            tt,
+            site_name,
+            page_size,
+            feeds,
+        })
+    }
+
+    /// Render the blog cards for a list of posts
+    /// root: The root_prefix of the page the cards are shown on
+    fn render_cards(&self, posts: &[&SourcePost], root: &str) -> Result<String> {
+        let mut rendered_blog_cards = String::new();
+        for post in posts {
+            let front_matter = &post.front_matter;
+            let blog_card_context = BlogCardContext {
+                filename: relative_url(root, &post_url(&post.slug)),
+                title: post.title.clone(),
+                date: post.display_date(),
+                updated: post.display_updated(),
+                excerpt: front_matter.excerpt.clone().unwrap_or_default(),
+                image: front_matter.image.clone().unwrap_or_default(),
+                sitename: self.site_name.to_string(),
+                tags: front_matter.tags.join(", "),
+                tag_links: tags::tag_links(&front_matter.tags, root),
+                extra: front_matter.extra.clone(),
+            };
+            rendered_blog_cards.push_str(
+                &self
+                    .tt
+                    .render("card", &blog_card_context)
+                    .context("Failed templating the blog card context")?,
+            );
+        }
+        Ok(rendered_blog_cards)
+    }
+
+    /// Render a post's page to `posts/<slug>.html`
+    fn write_post(&self, post: &SourcePost, out_path: &Path) -> Result<()> {
+        let front_matter = &post.front_matter;
+        let root = root_prefix(&post_url(&post.slug));
+        let post_context = PostContext {
+            title: post.title.clone(),
+            content: post.content.clone(),
+            date: post.display_date(),
+            updated: post.display_updated(),
+            excerpt: front_matter.excerpt.clone().unwrap_or_default(),
+            image: front_matter.image.clone().unwrap_or_default(),
+            tags: front_matter.tags.join(", "),
+            tag_links: tags::tag_links(&front_matter.tags, &root),
+            sitename: self.site_name.to_string(),
+            extra: front_matter.extra.clone(),
+            root,
+        };
+        let rendered_post = self
+            .tt
+            .render("post", &post_context)
+            .context("Failed templating the post context")?;
+        let post_file_path = out_path.join("posts").join(format!("{}.html", post.slug));
+        fs::write(&post_file_path, rendered_post)
+            .with_context(|| format!("Failed to write {}", post_file_path.display()))?;
+        println!(">> Templated post: {}", post_file_path.display());
+        Ok(())
+    }
+
+    /// Render a listing of posts with the index template, split into pages
+    /// The first page is `<url_path>index.html`, the rest `<url_path>page/<n>/index.html`
+    /// url_path: The path of the listing from the site root, e.g. `/tags/rust/`
+    /// title: The page title
+    /// heading: The heading shown at the top of the page
+    fn write_listing(
+        &self,
+        posts: &[&SourcePost],
+        out_path: &Path,
+        url_path: &str,
+        title: String,
+        heading: String,
+    ) -> Result<()> {
+        let pages = paginate(posts, self.page_size);
+        let total_pages = pages.len();
+
+        for (i, page_posts) in pages.into_iter().enumerate() {
+            let page = i + 1;
+            let root = root_prefix(&page_url(url_path, page));
+            let index_context = IndexContext {
+                title: title.clone(),
+                heading: heading.clone(),
+                sitename: self.site_name.to_string(),
+                blog_cards: self.render_cards(page_posts, &root)?,
+                feeds: self.feeds,
+                page,
+                total_pages,
+                prev_url: if page > 1 {
+                    relative_url(&root, &page_url(url_path, page - 1))
+                } else {
+                    String::new()
+                },
+                next_url: if page < total_pages {
+                    relative_url(&root, &page_url(url_path, page + 1))
+                } else {
+                    String::new()
+                },
+                root,
+            };
+            let rendered_index = self
+                .tt
+                .render("index", &index_context)
+                .context("Failed templating the index context")?;
+
+            let dir = out_path.join(page_url(url_path, page).trim_start_matches('/'));
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            fs::write(dir.join("index.html"), rendered_index)
+                .with_context(|| format!("Failed to write {}/index.html", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Render the tag overview to `tags/index.html`
+    fn write_tag_overview(&self, tags: &[Tag], out_path: &Path) -> Result<()> {
+        let max_count = tags.iter().map(|tag| tag.posts.len()).max().unwrap_or(1);
+        let root = root_prefix("/tags/");
+        let tags_context = TagsContext {
+            sitename: self.site_name.to_string(),
+            tags: tags
+                .iter()
+                .map(|tag| TagCloudEntry {
+                    name: tag.name.clone(),
+                    url: relative_url(&root, &tags::tag_url(&tag.slug)),
+                    count: tag.posts.len(),
+                    // scale the font between 1em and 2em by how often the tag is used
+                    size: format!("{:.2}", 1.0 + tag.posts.len() as f64 / max_count as f64),
+                })
+                .collect(),
+            root,
+        };
+        let rendered_tags = self
+            .tt
+            .render("tags", &tags_context)
+            .context("Failed templating the tags context")?;
+        let tags_path = out_path.join("tags");
+        fs::create_dir_all(&tags_path).context("Failed to create the tags directory")?;
+        fs::write(tags_path.join("index.html"), rendered_tags)
+            .context("Failed to write tags/index.html")?;
+        Ok(())
+    }
+}
+
+/// Template every post page, the index and the tag pages into the given directory
+fn render_site(
+    renderer: &Renderer,
+    posts: &[SourcePost],
+    tags: &[Tag],
+    out_path: &Path,
+) -> Result<()> {
+    println!(">> Templating HTML");
+    let site_name = renderer.site_name;
+
+    for post in posts {
+        renderer.write_post(post, out_path)?;
+    }
+
+    println!(">> Templating index");
+    let all_posts = posts.iter().collect::<Vec<_>>();
+    renderer.write_listing(
+        &all_posts,
+        out_path,
+        "/",
+        site_name.to_string(),
+        site_name.to_string(),
+    )?;
+
+    println!(">> Templating tag pages");
+    for tag in tags {
+        renderer.write_listing(
+            &tag.posts,
+            out_path,
+            &tags::tag_url(&tag.slug),
+            format!("{} - {}", tag.name, site_name),
+            format!("Tagged: {}", tag.name),
+        )?;
+    }
+    renderer.write_tag_overview(tags, out_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_relative_to_the_page() {
+        assert_eq!(root_prefix("/"), "");
+        assert_eq!(root_prefix("/posts/hello.html"), "../");
+        assert_eq!(root_prefix("/tags/rust/page/2/"), "../../../../");
+
+        assert_eq!(relative_url("", "/"), "index.html");
+        assert_eq!(relative_url("../", "/tags/"), "../tags/index.html");
+        assert_eq!(
+            relative_url("../../", "/posts/hello.html"),
+            "../../posts/hello.html"
+        );
+    }
+
+    fn now() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2024-06-01 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn front_matter_slugs_stay_inside_posts() {
+        let post =
+            SourcePost::parse("x", "---\nslug: ../../etc/Hello World\n---\nHi", now()).unwrap();
+        assert_eq!(post.slug, "etc-Hello-World");
+
+        assert!(SourcePost::parse("x", "---\nslug: ../..\n---\nHi", now()).is_err());
+    }
+
+    #[test]
+    fn duplicate_slugs_fail_the_build() {
+        let first = SourcePost::parse("first", "---\nslug: same\n---\nHi", now()).unwrap();
+        let second = SourcePost::parse("second", "---\nslug: same\n---\nHi", now()).unwrap();
+        let other = SourcePost::parse("other", "Hi", now()).unwrap();
+
+        assert!(check_unique_slugs(&[first, other]).is_ok());
+        let first = SourcePost::parse("first", "---\nslug: same\n---\nHi", now()).unwrap();
+        assert!(check_unique_slugs(&[first, second]).is_err());
+    }
+
+    fn posts(count: usize) -> Vec<SourcePost> {
+        (1..=count)
+            .map(|i| SourcePost::parse(&format!("post-{}", i), "Hi", now()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn paginate_splits_listings_at_page_boundaries() {
+        let posts = posts(7);
+        let all = posts.iter().collect::<Vec<_>>();
+        let sizes = |page_size| {
+            paginate(&all, page_size)
+                .iter()
+                .map(|page| page.len())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sizes(3), [3, 3, 1]);
+        assert_eq!(sizes(7), [7]);
+        assert_eq!(sizes(8), [7]);
+        assert_eq!(sizes(1).len(), 7);
+        assert_eq!(sizes(0), [7]);
+        assert_eq!(paginate(&all[..6], 3).len(), 2);
+        // a site without posts still gets its one, empty, index page
+        assert_eq!(paginate(&[], 3).len(), 1);
+    }
+
+    #[test]
+    fn listing_pages_link_to_their_neighbours() {
+        assert_eq!(page_url("/", 1), "/");
+        assert_eq!(page_url("/", 2), "/page/2/");
+        assert_eq!(page_url("/tags/rust/", 3), "/tags/rust/page/3/");
+
+        let out = tempfile::tempdir().unwrap();
+        let posts = posts(5);
+        let all = posts.iter().collect::<Vec<_>>();
+        let renderer = Renderer::new("Blog", 2, true).unwrap();
+        renderer
+            .write_listing(&all, out.path(), "/", "Blog".into(), "Blog".into())
+            .unwrap();
+
+        let first = fs::read_to_string(out.path().join("index.html")).unwrap();
+        assert!(first.contains("href=\"page/2/index.html\""));
+        assert!(first.contains("href=\"feed.xml\""));
+        assert!(!first.contains("Newer posts"));
+        assert!(first.contains("href=\"posts/post-1.html\""));
+
+        let middle = fs::read_to_string(out.path().join("page/2/index.html")).unwrap();
+        assert!(middle.contains("href=\"../../index.html\" class=\"pagination__link\""));
+        assert!(middle.contains("href=\"../../page/3/index.html\""));
+
+        let last = fs::read_to_string(out.path().join("page/3/index.html")).unwrap();
+        assert!(!last.contains("Older posts"));
+        assert!(!out.path().join("page/4").exists());
+    }
+
+    #[test]
+    fn listings_only_link_feeds_that_are_written() {
+        let out = tempfile::tempdir().unwrap();
+        let posts = posts(1);
+        let all = posts.iter().collect::<Vec<_>>();
+        Renderer::new("Blog", 0, false)
+            .unwrap()
+            .write_listing(&all, out.path(), "/", "Blog".into(), "Blog".into())
+            .unwrap();
+
+        let index = fs::read_to_string(out.path().join("index.html")).unwrap();
+        assert!(!index.contains("rel=\"alternate\""));
+    }
+
+    #[test]
+    fn sitemap_lists_every_page_with_the_newest_date_it_shows() {
+        let source =
+            |date: &str, tags: &str| format!("---\ndate: {}\ntags: [{}]\n---\nHi", date, tags);
+        let posts = vec![
+            SourcePost::parse(
+                "new",
+                "---\ndate: 2024-03-01\nupdated: 2024-05-01\ntags: [rust]\n---\nHi",
+                now(),
+            )
+            .unwrap(),
+            SourcePost::parse("middle", &source("2024-02-01", "rust"), now()).unwrap(),
+            SourcePost::parse("old", &source("2024-01-01", "web"), now()).unwrap(),
+        ];
+        let tags = tags::collect_tags(&posts);
+        let entries = sitemap_entries(&posts, &tags, 2);
+
+        let date = |date: &str| crate::front_matter::parse_date(date);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.path.as_str(), entry.lastmod))
+                .collect::<Vec<_>>(),
+            [
+                ("/", date("2024-05-01")),
+                ("/page/2/", date("2024-01-01")),
+                ("/posts/new.html", date("2024-05-01")),
+                ("/posts/middle.html", date("2024-02-01")),
+                ("/posts/old.html", date("2024-01-01")),
+                ("/tags/", date("2024-05-01")),
+                ("/tags/rust/", date("2024-05-01")),
+                ("/tags/web/", date("2024-01-01")),
+            ]
+        );
+    }
+
+    #[test]
+    fn feeds_and_sitemap_need_a_base_url() {
+        let site = crate::hosting::new_local_site("blog");
+        let posts = posts(1);
+
+        let out = tempfile::tempdir().unwrap();
+        fs::create_dir(out.path().join("posts")).unwrap();
+        write_site(&site, &SiteConfig::default(), &posts, out.path()).unwrap();
+        assert!(out.path().join("index.html").exists());
+        assert!(!out.path().join(sitemap::SITEMAP_FILE).exists());
+        assert!(!out.path().join(sitemap::ROBOTS_FILE).exists());
+
+        let out = tempfile::tempdir().unwrap();
+        fs::create_dir(out.path().join("posts")).unwrap();
+        let config = SiteConfig {
+            base_url: Some("https://blog.example.com/".to_string()),
+            ..SiteConfig::default()
+        };
+        write_site(&site, &config, &posts, out.path()).unwrap();
+        let sitemap = fs::read_to_string(out.path().join(sitemap::SITEMAP_FILE)).unwrap();
+        assert!(sitemap.contains("<loc>https://blog.example.com/posts/post-1.html</loc>"));
+        assert!(out.path().join(sitemap::ROBOTS_FILE).exists());
+    }
+
+    /// Render a site with one post whose front matter is full of HTML special characters
+    fn render_special_characters() -> tempfile::TempDir {
+        let source = "---\ntitle: Fish & \"Chips\"\nexcerpt: Say \"hi\" <now>\ntags: [\"<b>bold</b>\"]\n---\n**Hi**";
+        let posts = vec![SourcePost::parse("fish", source, now()).unwrap()];
+        let tags = tags::collect_tags(&posts);
+        let out = tempfile::tempdir().unwrap();
+        fs::create_dir(out.path().join("posts")).unwrap();
+        render_site(
+            &Renderer::new("Tom's <Blog>", 0, false).unwrap(),
+            &posts,
+            &tags,
+            out.path(),
+        )
+        .unwrap();
+        out
+    }
+
+    #[test]
+    fn front_matter_text_is_escaped_on_post_pages() {
+        let out = render_special_characters();
+        let post = fs::read_to_string(out.path().join("posts/fish.html")).unwrap();
+        assert!(
+            post.contains(r#"<meta name="description" content="Say &quot;hi&quot; &lt;now&gt;">"#)
+        );
+        assert!(post.contains("<h1>Fish &amp; &quot;Chips&quot;</h1>"));
+        assert!(post.contains("#&lt;b&gt;bold&lt;/b&gt;</a>"));
+        assert!(post.contains("Tom&#39;s &lt;Blog&gt;"));
+        // the post's own markdown is still rendered as HTML
+        assert!(post.contains("<strong>Hi</strong>"));
+    }
+
+    #[test]
+    fn titles_and_tags_are_escaped_on_cards_and_tag_pages() {
+        let out = render_special_characters();
+        let index = fs::read_to_string(out.path().join("index.html")).unwrap();
+        assert!(index.contains(r#"data-title="Fish &amp; &quot;Chips&quot;""#));
+        assert!(index.contains(r#"alt="Fish &amp; &quot;Chips&quot;""#));
+        assert!(index.contains(r#"<p class="card__excerpt">Say &quot;hi&quot; &lt;now&gt;</p>"#));
+        assert!(!index.contains("<b>bold</b>"));
+
+        let tag_page = fs::read_to_string(out.path().join("tags/b-bold-b/index.html")).unwrap();
+        assert!(tag_page.contains("<h1>Tagged: &lt;b&gt;bold&lt;/b&gt;</h1>"));
+        assert!(
+            tag_page.contains("<title>&lt;b&gt;bold&lt;/b&gt; - Tom&#39;s &lt;Blog&gt;</title>")
+        );
+        let overview = fs::read_to_string(out.path().join("tags/index.html")).unwrap();
+        assert!(overview.contains("&lt;b&gt;bold&lt;/b&gt; <span"));
+    }
+
+    #[test]
+    fn listings_and_the_tag_overview_share_their_styles() {
+        let out = render_special_characters();
+        let shared = SHARED_STYLES_TEMPLATE.replace("\\{", "{");
+        for page in ["index.html", "tags/index.html", "tags/b-bold-b/index.html"] {
+            let html = fs::read_to_string(out.path().join(page)).unwrap();
+            assert!(html.contains(&shared), "{}", page);
+            assert_eq!(html.matches(".mode-toggle:hover {").count(), 1, "{}", page);
+        }
+    }
+}
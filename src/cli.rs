@@ -1,11 +1,142 @@
-use crate::netlify::{Netlify, Ssl_Cert};
-use anyhow::{Context, Result};
-use driftwood::{Git, Post, SiteDetails, read_and_parse, template_html};
-use std::{fs, io::Write, path::Path, vec};
+use crate::netlify::error::NetlifyError;
+use crate::netlify::token::AUTH_TOKEN_VAR;
+use crate::netlify::Netlify;
+use anyhow::{bail, Context, Result};
+use driftwood::build::{build_site, BuildOptions, PREVIEW_DIR};
+use driftwood::export::ExportTarget;
+use driftwood::git_pages::GitPagesTarget;
+use driftwood::history::{
+    append_history, history_path, read_history, record_deploy, site_commit, DeployRecord,
+};
+use driftwood::hosting::{Deployment, HostingProvider, SslCert};
+use driftwood::s3::S3Target;
+use driftwood::sftp::SftpTarget;
+use driftwood::{Git, Post, SiteDetails};
+use std::{
+    env, fs,
+    io::{IsTerminal, Write},
+    path::Path,
+    vec,
+};
 
 // TODO - Seperate all the logic that involves building files or interacting with the Netlify API to lib.rs.
 // TODO - Implement tui-rs for a better user experience
 
+/// Run the command given on the command line, or draw the menu when there isn't one
+/// args: The arguments after the program name
+pub fn run(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        None => draw_menu(),
+        Some("login") => login(&args[1..]),
+        Some("logout") => logout(),
+        Some("whoami") => whoami(),
+        Some(command) => {
+            print_error_message(&format!("Unknown command: {}", command))?;
+            println!("Commands: login [--with-token | --token-file <path>], logout, whoami");
+            Ok(())
+        }
+    }
+}
+
+/// Log in to Netlify, replacing the saved token
+/// With no options this uses the personal access token in NETLIFY_AUTH_TOKEN, or the browser if it isn't set
+/// `--with-token` reads a personal access token from stdin
+/// `--token-file <path>` reads a personal access token from a file
+fn login(args: &[String]) -> Result<()> {
+    let netlify = match login_source(args, env::var(AUTH_TOKEN_VAR).ok(), read_token)? {
+        Some(LoginSource::Token(token)) => Netlify::log_in_with_token(&token)?,
+        Some(LoginSource::Browser) => Netlify::log_in_and_save()?,
+        None => {
+            return print_error_message(
+                "Usage: driftwood login [--with-token | --token-file <path>]",
+            )
+        }
+    };
+    print_current_user(&netlify)
+}
+
+/// LoginSource enum
+/// Where `driftwood login` gets its token from
+/// Token: A personal access token, trimmed, from stdin, a file or NETLIFY_AUTH_TOKEN
+/// Browser: Logging in through the browser
+#[derive(Debug, PartialEq)]
+enum LoginSource {
+    Token(String),
+    Browser,
+}
+
+/// Work out where `driftwood login` gets its token from, None if the options aren't valid
+/// env_token: The value of NETLIFY_AUTH_TOKEN, if it's set
+/// read_stdin: Reads a token from stdin, only called for `--with-token`
+fn login_source(
+    args: &[String],
+    env_token: Option<String>,
+    read_stdin: impl FnOnce() -> Result<String>,
+) -> Result<Option<LoginSource>> {
+    let token = match args {
+        [] => match env_token {
+            Some(token) => token,
+            None => return Ok(Some(LoginSource::Browser)),
+        },
+        [option] if option == "--with-token" => read_stdin()?,
+        [option, path] if option == "--token-file" => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the token from {}", path))?,
+        _ => return Ok(None),
+    };
+
+    let token = token.trim();
+    if token.is_empty() {
+        bail!("The personal access token is empty");
+    }
+    Ok(Some(LoginSource::Token(token.to_string())))
+}
+
+/// Read a personal access token from stdin, asking for it when stdin is a terminal
+fn read_token() -> Result<String> {
+    if std::io::stdin().is_terminal() {
+        print!("Paste a Netlify personal access token: ");
+        std::io::stdout()
+            .flush()
+            .context("Failed to flush stdout")?;
+    }
+
+    let mut token = String::new();
+    std::io::stdin()
+        .read_line(&mut token)
+        .context("Failed to read the token")?;
+    Ok(token)
+}
+
+/// Remove the saved Netlify token
+fn logout() -> Result<()> {
+    Netlify::log_out()?;
+    println!("Logged out of Netlify");
+    Ok(())
+}
+
+/// Show who the saved Netlify token belongs to
+fn whoami() -> Result<()> {
+    match Netlify::load()? {
+        Some(netlify) => print_current_user(&netlify),
+        None => {
+            println!("Not logged in to Netlify, run `driftwood login`");
+            Ok(())
+        }
+    }
+}
+
+/// Print the user a Netlify connection is logged in as, and where its token is kept
+fn print_current_user(netlify: &Netlify) -> Result<()> {
+    let user = netlify.get_current_user()?;
+    println!(
+        "Logged in to Netlify as {} <{}>",
+        user.full_name.unwrap_or_default(),
+        user.email.unwrap_or_default()
+    );
+    println!("Token from the {}", netlify.token_source());
+    Ok(())
+}
+
 /// Draws the menu and all the options
 pub fn draw_menu() -> Result<()> {
     loop {
@@ -25,12 +156,16 @@ pub fn draw_menu() -> Result<()> {
             .read_line(&mut input)
             .context("Failed to read line")?;
 
-        let _ = match input.trim() {
+        let result = match input.trim() {
             "1" => create_website(),
             "2" => list_websites(),
             "q" => break,
             _ => print_error_message("Invalid option. Please try again."),
         };
+        // e.g. not being logged in, the menu stays open so the user can try again
+        if let Err(e) = result {
+            println!("Error: {:#}", e);
+        }
     }
     Ok(())
 }
@@ -85,6 +220,17 @@ fn create_post(site: &SiteDetails) -> Result<()> {
     // remove special chars and set post.tags
     new_post.clean_and_set_tags(input)?;
 
+    println!("Save the post as a draft? Drafts are not deployed. (y/n)");
+    print!("> ");
+    let mut input = String::new();
+    std::io::stdout()
+        .flush()
+        .context("Failed to flush stdout")?;
+    std::io::stdin()
+        .read_line(&mut input)
+        .context("Failed to read line")?;
+    new_post.draft = input.trim().eq_ignore_ascii_case("y");
+
     // write the post to disk
     match new_post.write_post_to_disk(site) {
         Ok(_) => println!("Post written to disk."),
@@ -92,7 +238,8 @@ fn create_post(site: &SiteDetails) -> Result<()> {
     }
 
     if !site.check_for_site_repo()? {
-        site.create_site_repo().context("Failed to initialize new repository")?
+        site.create_site_repo()
+            .context("Failed to initialize new repository")?
     }
 
     Post::commit_post_to_repo(site, &format!("Add new post: {}", new_post.title))?;
@@ -130,13 +277,26 @@ fn create_website() -> Result<()> {
         return Ok(());
     }
 
-    let website_name = input.trim().to_string();
-
-    let netlify: Netlify = Netlify::new();
-    let site_details = create_site(netlify, website_name).expect("Failed to create site");
+    let mut website_name = input.trim().to_string();
+
+    let provider = hosting_provider()?;
+    let site_details = loop {
+        match create_site(provider.as_ref(), website_name.clone()) {
+            Ok(site_details) => break site_details,
+            Err(e) if is_name_conflict(&e) => match ask_for_new_name(&website_name)? {
+                Some(new_name) => website_name = new_name,
+                None => return Ok(()),
+            },
+            Err(_) => {
+                println!("Failed to create the site.");
+                return Ok(());
+            }
+        }
+    };
     make_site_dir(&site_details);
 
-    let repo = Git::init_git_repo(&site_details.name.clone().unwrap());
+    let site_path = site_details.build_site_path()?;
+    let repo = Git::init_git_repo(&site_path.to_string_lossy());
     Git::commit_changes(&repo.unwrap(), "Initial commit")?;
 
     println!("Press enter to return to the main menu.");
@@ -150,8 +310,8 @@ fn create_website() -> Result<()> {
 
 fn list_websites() -> Result<()> {
     // grab all the sites
-    let netlify: Netlify = Netlify::new();
-    let site_details: Vec<SiteDetails> = get_sites(netlify);
+    let provider = hosting_provider()?;
+    let site_details: Vec<SiteDetails> = get_sites(provider.as_ref());
 
     println!("Your Websites");
     println!("---------------------------------------");
@@ -189,9 +349,9 @@ fn list_websites() -> Result<()> {
         .iter()
         .find(|site| site.name.clone().unwrap() == site_id);
 
-    if site.is_some() {
-        make_site_dir(site.unwrap());
-        update_site(site.unwrap())?;
+    if let Some(site) = site {
+        make_site_dir(site);
+        update_site(site)?;
     }
 
     Ok(())
@@ -201,7 +361,7 @@ fn update_site(site: &SiteDetails) -> Result<()> {
     println!("Name: {}", site.name.clone().unwrap());
     println!("Id: {}", site.name.clone().unwrap());
     println!("URL: {}", site.name.clone().unwrap());
-    println!("SSL: {}", site.ssl.clone().unwrap());
+    println!("SSL: {}", site.ssl.unwrap());
     println!("---------------------------------------");
     println!("Options:");
     println!("1. Create a blog post");
@@ -209,6 +369,13 @@ fn update_site(site: &SiteDetails) -> Result<()> {
     println!("3. Update the site's name");
     println!("4. Delete the site");
     println!("5. Provision an SSL certificate");
+    println!("6. List the site's posts");
+    println!("7. Build a local preview (includes drafts)");
+    println!("8. Export the site to a directory or .zip");
+    println!("9. List previous deploys");
+    println!("10. Roll back to a previous deploy");
+    println!("11. Deploy a preview without going live");
+    println!("12. Promote a preview to live");
     println!("Type 'q' to return to the main menu.");
     print!("> ");
     std::io::stdout()
@@ -235,6 +402,13 @@ fn update_site(site: &SiteDetails) -> Result<()> {
         "3" => update_site_name(site),
         "4" => delete_site(site),
         "5" => create_ssl_certificate(site),
+        "6" => list_posts(site),
+        "7" => build_preview(site),
+        "8" => export_site(site),
+        "9" => list_deploys(site),
+        "10" => rollback_site(site),
+        "11" => preview_site(site),
+        "12" => promote_preview(site),
         _ => print_error_message("Invalid option. Returning to main menu."),
     }?;
 
@@ -268,15 +442,100 @@ fn make_site_dir(site: &SiteDetails) {
     println!("Path: {}", path.display());
 
     if !path.exists() {
-        fs::create_dir(path).expect(&format!(
-            "Failed to create site directory for site: {}",
-            site.name.clone().unwrap()
-        ));
+        fs::create_dir(path).unwrap_or_else(|_| {
+            panic!(
+                "Failed to create site directory for site: {}",
+                site.name.clone().unwrap()
+            )
+        });
+    }
+}
+
+fn list_posts(site: &SiteDetails) -> Result<()> {
+    println!("Posts for: {}", site.name.clone().unwrap());
+    println!("---------------------------------------");
+
+    match driftwood::build::list_posts(site) {
+        Ok(posts) if posts.is_empty() => println!("No posts yet."),
+        Ok(posts) => {
+            for post in posts {
+                println!("{} | {} | {}", post.date, post.title, post.state);
+            }
+        }
+        Err(e) => println!("Error: {:?}", e),
+    }
+
+    println!("---------------------------------------");
+    println!("Press enter to return to the main menu.");
+    print!("> ");
+    std::io::stdout()
+        .flush()
+        .context("Failed to flush stdout")?;
+    std::io::stdin()
+        .read_line(&mut String::new())
+        .context("Failed to read line")?;
+    Ok(())
+}
+
+fn build_preview(site: &SiteDetails) -> Result<()> {
+    let options = BuildOptions {
+        output_dir: Some(SiteDetails::build_site_path(site)?.join(PREVIEW_DIR)),
+        include_drafts: true,
+    };
+
+    match build_site(site, &options) {
+        Ok(output_path) => {
+            println!("Preview built, drafts and scheduled posts included.");
+            println!(
+                "Open {} in your browser.",
+                output_path.join("index.html").display()
+            );
+        }
+        Err(e) => {
+            println!("Failed to build the preview.");
+            println!("Error: {:?}", e);
+        }
+    }
+
+    println!("Press enter to return to the main menu.");
+    print!("> ");
+    std::io::stdout()
+        .flush()
+        .context("Failed to flush stdout")?;
+    std::io::stdin()
+        .read_line(&mut String::new())
+        .context("Failed to read line")?;
+    Ok(())
+}
+
+fn export_site(site: &SiteDetails) -> Result<()> {
+    println!("Enter the directory to export the site to.");
+    println!("End the path with '.zip' to export a .zip archive instead.");
+    println!("Type 'q' to return to the main menu.");
+    print!("> ");
+    std::io::stdout()
+        .flush()
+        .context("Failed to flush stdout")?;
+
+    let mut input = String::new();
+    std::io::stdin()
+        .read_line(&mut input)
+        .context("Failed to read line")?;
+
+    if input.trim() == "q" {
+        return Ok(());
+    }
+
+    if !check_input_length(&input, 2) {
+        return Ok(());
     }
+
+    let export_target = ExportTarget::new(input.trim());
+    deploy_to(&export_target, site, false)
 }
 
 fn update_site_name(site: &SiteDetails) -> Result<()> {
-    let netlify: Netlify = Netlify::new();
+    let provider = hosting_provider()?;
 
     println!("Name: {}", site.name.clone().unwrap());
     println!("Enter the new name of your website.");
@@ -297,164 +556,327 @@ fn update_site_name(site: &SiteDetails) -> Result<()> {
         return Ok(());
     }
 
-    let site_name = input.trim().to_string();
+    let mut site_name = input.trim().to_string();
+
+    loop {
+        let mut new_site = site.clone();
+        new_site.name = Some(site_name.clone());
+
+        match update_site_details(provider.as_ref(), site, new_site) {
+            Err(e) if is_name_conflict(&e) => match ask_for_new_name(&site_name)? {
+                Some(new_name) => site_name = new_name,
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        }
+    }
+}
 
-    let mut new_site = site.clone();
-    new_site.name = Some(site_name);
-    let existing_site = site.clone();
+/// Whether an error is the hosting provider saying a site name is taken
+fn is_name_conflict(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<NetlifyError>(),
+        Some(NetlifyError::NameConflict { .. })
+    )
+}
 
-    let _ = update_site_details(netlify, existing_site, new_site);
+/// Ask for another site name after one turned out to be taken
+/// Returns None if the user gives up
+fn ask_for_new_name(taken: &str) -> Result<Option<String>> {
+    loop {
+        println!("The name '{}' is already taken.", taken);
+        println!("Enter a different name for your website.");
+        println!("Type 'q' to return to the main menu.");
+        print!("> ");
+        std::io::stdout()
+            .flush()
+            .context("Failed to flush stdout")?;
 
-    Ok(())
+        let mut input = String::new();
+        std::io::stdin()
+            .read_line(&mut input)
+            .context("Failed to read line")?;
+
+        match input.trim() {
+            "q" => return Ok(None),
+            name if name.len() < 2 => println!("Invalid entry"),
+            name => return Ok(Some(name.to_string())),
+        }
+    }
 }
 
 fn deploy_site(site: &SiteDetails) -> Result<()> {
-    let netlify: Netlify = Netlify::new();
-
-    // first loop through the site's posts and convert them to HTML
-    let site_path = SiteDetails::build_site_path(site)?;
-    // convert site_path PathBuf to string
-    let site_path = site_path.to_string_lossy().to_string();
-    let post_path = format!("{}/md_posts", site_path);
-    let html_post_path = format!("{}/posts", site_path);
-
-    // lol bad names... it's the PATH types of these string paths
-    let post_path_path = Path::new(&post_path);
-    let html_post_path_path = Path::new(&html_post_path);
-    if !post_path_path.exists() {
-        fs::create_dir(post_path_path).expect("Failed to create this site's 'md_posts' directory");
-    }
-    if !html_post_path_path.exists() {
-        fs::create_dir(html_post_path_path)
-            .expect("Failed to create this site's 'md_posts' directory");
-    }
-
-    let mut html_file_names = vec![];
-
-    // loop through md posts
-    for entry in fs::read_dir(post_path).unwrap() {
-        // md filename
-        let entry = entry.unwrap();
-        println!("> {:?}", entry.file_name().to_string_lossy());
-        // full path to md file
-        let md_file_name = entry.path();
-        println!("> {:?}", md_file_name);
-        // full path to html file
-        let html_file_name = format!(
-            "{}/posts/{}.html",
-            site_path,
-            entry.file_name().to_string_lossy()
-        );
-        println!("> {:?}", html_file_name);
-
-        if md_file_name.is_file() {
-            let md_file_name = md_file_name.to_string_lossy();
-            // let html_file_name = html_file_name.file_name().unwrap().to_string_lossy().into_owned();
-            // convert to html
-            let success = read_and_parse(&md_file_name, &html_file_name);
-            match success {
-                Ok(_) => {
-                    println!("Successfully converted markdown to HTML.");
-                    // add this html file name to a vector of strings
-                    html_file_names.push(html_file_name);
-                }
-                Err(e) => {
-                    println!("Failed to convert markdown to HTML.");
-                    println!("Error: {:?}", e);
-                }
+    let provider = hosting_provider()?;
+    deploy_to(provider.as_ref(), site, false)
+}
+
+fn preview_site(site: &SiteDetails) -> Result<()> {
+    let provider = hosting_provider()?;
+    deploy_to(provider.as_ref(), site, true)
+}
+
+/// Build the site and hand the output to a hosting provider
+/// preview: Deploy a preview that leaves the live site alone
+fn deploy_to(provider: &dyn HostingProvider, site: &SiteDetails, preview: bool) -> Result<()> {
+    // build the site into its output directory, the markdown sources are left untouched
+    let output_path = match build_site(site, &BuildOptions::default()) {
+        Ok(output_path) => {
+            println!("Successfully built the site.");
+            output_path
+        }
+        Err(e) => {
+            println!("Failed to build the site.");
+            println!("Error: {:?}", e);
+            println!("Press enter to return to the main menu.");
+            print!("> ");
+            std::io::stdin().read_line(&mut String::new())?;
+            return Ok(());
+        }
+    };
+
+    let result = if preview {
+        provider.preview(site, &output_path)
+    } else {
+        provider.deploy(site, &output_path)
+    };
+
+    let record = match result {
+        Ok(deployment) if preview => {
+            println!(
+                "Preview deployed to {}, the live site is unchanged.",
+                provider.name()
+            );
+            if let Some(id) = &deployment.id {
+                println!("Deploy ID: {}", id);
+            }
+            if let Some(url) = &deployment.url {
+                println!("Preview URL: {}", url);
+            }
+            println!("Use option 12 to put this deploy live once it's been reviewed.");
+            DeployRecord::draft(provider.name(), deployment.id, deployment.url)
+        }
+        Ok(deployment) => {
+            println!("Site deployed to {}.", provider.name());
+            if let Some(id) = &deployment.id {
+                println!("Deploy ID: {}", id);
+            }
+            if let Some(url) = &deployment.url {
+                println!("URL: {}", url);
             }
+            DeployRecord::ready(provider.name(), deployment.id, deployment.url)
         }
+        Err(e) => {
+            println!("Failed to deploy the site.");
+            println!("Error: {:#}", e);
+            DeployRecord::failed(provider.name(), format!("{:#}", e))
+        }
+    }
+    .with_commit(site_commit(site));
+
+    // a deploy that went out shouldn't be reported as failed because the log couldn't be written
+    if let Err(e) = record_deploy(site, record) {
+        println!("Failed to record the deploy in the site's history: {:#}", e);
+    }
+
+    Ok(())
+}
+
+fn list_deploys(site: &SiteDetails) -> Result<()> {
+    let provider = hosting_provider()?;
+
+    println!("Deploys for: {}", site.name.clone().unwrap());
+    println!("---------------------------------------");
+    match provider.list_deploys(site) {
+        Ok(deploys) if deploys.is_empty() => println!("No deploys yet."),
+        Ok(deploys) => {
+            for deploy in deploys {
+                println!(
+                    "{} | {} | {} | {} | {}",
+                    deploy.id,
+                    deploy.created_at.unwrap_or_default(),
+                    if deploy.live {
+                        format!("{} (live)", deploy.state.unwrap_or_default())
+                    } else if deploy.draft {
+                        format!("{} (preview)", deploy.state.unwrap_or_default())
+                    } else {
+                        deploy.state.unwrap_or_default()
+                    },
+                    deploy
+                        .commit
+                        .map(|commit| commit.chars().take(7).collect())
+                        .unwrap_or_else(|| "-".to_string()),
+                    deploy.message.unwrap_or_default()
+                );
+            }
+        }
+        Err(e) => println!("Error: {:#}", e),
+    }
+    println!("---------------------------------------");
+    println!("Press enter to return to the main menu.");
+    print!("> ");
+    std::io::stdout()
+        .flush()
+        .context("Failed to flush stdout")?;
+    std::io::stdin()
+        .read_line(&mut String::new())
+        .context("Failed to read line")?;
+    Ok(())
+}
+
+fn rollback_site(site: &SiteDetails) -> Result<()> {
+    let provider = hosting_provider()?;
+
+    println!("Enter the ID of the deploy to roll back to.");
+    println!("Use option 9 to list the site's deploys.");
+    println!("Press enter to roll back to the deploy before the live one.");
+    println!("Type 'q' to return to the main menu.");
+    print!("> ");
+    std::io::stdout()
+        .flush()
+        .context("Failed to flush stdout")?;
+
+    let mut input = String::new();
+    std::io::stdin()
+        .read_line(&mut input)
+        .context("Failed to read line")?;
+
+    if input.trim() == "q" {
+        return Ok(());
     }
 
-    // remove any dashes or underscores from the site name, replace with spaces
-    let clean_site_name = site
-        .name
-        .clone()
-        .unwrap()
-        .replace("-", " ")
-        .replace("_", " ");
-    let template_success = template_html(html_file_names, site_path.clone(), clean_site_name);
-    match template_success {
-        Ok(_) => {
-            println!("Successfully templated blog links.");
+    let deploy_id = if input.trim().is_empty() {
+        match previous_deploy(provider.as_ref(), site) {
+            Ok(deploy_id) => deploy_id,
+            Err(e) => {
+                println!("Error: {:#}", e);
+                return Ok(());
+            }
+        }
+    } else {
+        input.trim().to_string()
+    };
+
+    match provider.rollback(site, &deploy_id) {
+        Ok(deployment) => {
+            println!("Rolled back to deploy {}.", deploy_id);
+            if let Some(url) = &deployment.url {
+                println!("URL: {}", url);
+            }
+            record_restore(provider.as_ref(), site, &deploy_id, deployment);
         }
         Err(e) => {
-            println!("Failed to template blog links.");
-            println!("Error: {:?}", e);
+            println!("Failed to roll back the site.");
+            println!("Error: {:#}", e);
         }
     }
 
-    // second generate the sha1 hash of all the html files
-    let post_path = format!("{}/posts", site_path);
+    Ok(())
+}
 
-    let site_path = Path::new(&site_path);
-    let post_path = Path::new(&post_path);
+/// The ID of the newest successful deploy created before the live one
+fn previous_deploy(provider: &dyn HostingProvider, site: &SiteDetails) -> Result<String> {
+    let deploys = provider.list_deploys(site)?;
+    // deploys are listed newest first, so everything after the live one is older
+    let live = deploys
+        .iter()
+        .position(|deploy| deploy.live)
+        .context("None of the site's deploys is live")?;
+    deploys[live + 1..]
+        .iter()
+        .find(|deploy| !deploy.draft && deploy.state.as_deref() == Some("ready"))
+        .map(|deploy| deploy.id.clone())
+        .context("There is no earlier deploy to roll back to")
+}
 
-    let sha1_result = Netlify::generate_sha1_for_posts(site_path, post_path);
+/// Log a deploy that was put live again in the site's history, so the history shows what's live
+/// Rollbacks and promoted previews are both recorded this way
+fn record_restore(
+    provider: &dyn HostingProvider,
+    site: &SiteDetails,
+    deploy_id: &str,
+    deployment: Deployment,
+) {
+    let result = history_path(site)
+        .and_then(|path| append_restore(provider.name(), &path, deploy_id, deployment));
+    if let Err(e) = result {
+        println!("Failed to record the deploy in the site's history: {:#}", e);
+    }
+}
 
-    if sha1_result.is_ok() {
-        println!("> SHA1 hash generated successfully.");
-    } else {
-        println!("> Error: {}", sha1_result.err().unwrap());
-        println!("Press enter to return to the main menu.");
-        print!("> ");
-        std::io::stdin().read_line(&mut String::new()).unwrap();
+/// Add a deploy that was put live again to a history file
+/// history_file: The site's history file
+fn append_restore(
+    provider_name: &str,
+    history_file: &Path,
+    deploy_id: &str,
+    deployment: Deployment,
+) -> Result<()> {
+    let history = read_history(history_file)?;
+    let record = DeployRecord::restored(provider_name, deploy_id, deployment.url, &history);
+    append_history(history_file, record)
+}
+
+fn promote_preview(site: &SiteDetails) -> Result<()> {
+    let provider = hosting_provider()?;
+
+    println!("Enter the ID of the preview to put live.");
+    println!("Press enter to promote the newest preview.");
+    println!("Type 'q' to return to the main menu.");
+    print!("> ");
+    std::io::stdout()
+        .flush()
+        .context("Failed to flush stdout")?;
+
+    let mut input = String::new();
+    std::io::stdin()
+        .read_line(&mut input)
+        .context("Failed to read line")?;
+
+    if input.trim() == "q" {
         return Ok(());
     }
 
-    // unwrap the result to get the FileHashes struct
-    let sha1_hashmap = sha1_result.unwrap();
-
-    // post the file hashes to netlify
-    let new_site = netlify.send_file_checksums(site.clone(), &sha1_hashmap);
+    let deploy_id = if input.trim().is_empty() {
+        match newest_preview(provider.as_ref(), site) {
+            Ok(deploy_id) => deploy_id,
+            Err(e) => {
+                println!("Error: {:#}", e);
+                return Ok(());
+            }
+        }
+    } else {
+        input.trim().to_string()
+    };
 
-    // make sure you don't overlap "site" and "new site"
-    // site is the og site details, new site is the deploy details + site details
-    // the id will overlap
-    match new_site {
-        Ok(new_site) => {
-            println!(">Site Details:");
-            println!("{:?}", new_site);
-
-            // loop over the site's required vector (unwrap to get outside the option)
-            new_site.required.unwrap().iter().for_each(|file| {
-                println!("> Required file: {:?}", file);
-
-                // loop through our hashmap of file hashes
-                sha1_hashmap.files.iter().for_each(|file_hash| {
-                    // destructure the tuple (apparently iterating through hashmaps gives you tuples)
-                    let (current_file_name, current_file_hash) = file_hash;
-                    // if they match, print
-                    if file == current_file_hash {
-                        println!("> Matching File hash: {:?}", file_hash);
-                        let response = netlify.upload_file(
-                            site.name.clone().unwrap(),
-                            site.id.clone().unwrap(),
-                            new_site.id.clone().unwrap(),
-                            Path::new(current_file_name),
-                        );
-                        match response {
-                            Ok(_) => {
-                                println!("> File uploaded successfully.");
-                            }
-                            Err(e) => {
-                                println!("> Error: {:?}", e);
-                            }
-                        }
-                    }
-                });
-            });
+    match provider.promote(site, &deploy_id) {
+        Ok(deployment) => {
+            println!("Deploy {} is now live.", deploy_id);
+            if let Some(url) = &deployment.url {
+                println!("URL: {}", url);
+            }
+            record_restore(provider.as_ref(), site, &deploy_id, deployment);
         }
         Err(e) => {
-            println!("Error: {:?}", e);
+            println!("Failed to promote the preview.");
+            println!("Error: {:#}", e);
         }
     }
 
     Ok(())
 }
 
+/// The ID of the newest preview that finished processing
+fn newest_preview(provider: &dyn HostingProvider, site: &SiteDetails) -> Result<String> {
+    provider
+        .list_deploys(site)?
+        .into_iter()
+        .find(|deploy| deploy.draft && deploy.state.as_deref() == Some("ready"))
+        .map(|deploy| deploy.id)
+        .context("There is no preview to promote")
+}
+
 fn delete_site(site: &SiteDetails) -> Result<()> {
-    let netlify: Netlify = Netlify::new();
+    let provider = hosting_provider()?;
 
     println!("Deleting: {}", site.name.clone().unwrap());
     println!("This will permanently delete the website.");
@@ -475,8 +897,10 @@ fn delete_site(site: &SiteDetails) -> Result<()> {
     if input.trim() == "q" {
         return Ok(());
     } else if input.trim() == "yes" {
-        let _ = netlify.delete_site(site.clone());
-        println!("Site deleted.");
+        match provider.delete_site(site) {
+            Ok(_) => println!("Site deleted."),
+            Err(e) => println!("Error: {:?}", e),
+        }
         println!("Press enter to return to the main menu.");
         print!("> ");
         std::io::stdin()
@@ -532,17 +956,17 @@ fn create_ssl_certificate(site: &SiteDetails) -> Result<()> {
         "Creating SSL certificate for site: {}",
         site.name.clone().unwrap()
     );
-    let netlify: Netlify = Netlify::new();
+    let provider = hosting_provider()?;
     let mut current_site = site.clone();
     current_site.ssl = Some(true);
 
-    let new_ssl_details = Ssl_Cert {
+    let new_ssl_details = SslCert {
         cert: Some(certificate),
         key: Some(key),
         ca_cert: Some(ca),
     };
 
-    let _ = provision_ssl(netlify, current_site, new_ssl_details);
+    let _ = provision_ssl(provider.as_ref(), &current_site, new_ssl_details);
     let mut input = String::new();
     println!("Press enter to return to the main menu.");
     print!("> ");
@@ -569,73 +993,37 @@ fn check_input_length(input: &str, length: usize) -> bool {
     success
 }
 
-/// Convert a markdown file to an HTML file via the command line
-fn convert_md_to_html_cli() -> Result<()> {
-    println!("Convert a markdown file to HTML:");
-    println!("---------------------------------------");
-    println!("Enter the name of the markdown file followed by the name of the output HTML file.");
-    println!("Type 'q' to return to the main menu.");
-    print!("> ");
-    std::io::stdout()
-        .flush()
-        .context("Failed to flush stdout")?;
-
-    let mut input = String::new();
-
-    std::io::stdin()
-        .read_line(&mut input)
-        .expect("Invalid entry for markdown and html filenames.");
-
-    // needs to be at least 3 characters long
-    if !check_input_length(&input, 3) {
-        return Ok(());
-    }
-
-    if input.trim() == "q" {
-        return Ok(());
-    }
-
-    let args: Vec<&str> = input.trim().split(" ").collect();
-
-    let success = read_and_parse(&args[0], &args[1]);
-    match success {
-        Ok(_) => {
-            println!("Successfully converted markdown to HTML.");
+/// The hosting provider the menus talk to
+/// Netlify unless DRIFTWOOD_PROVIDER is set to:
+/// `export`, which works offline and exports to DRIFTWOOD_EXPORT_PATH (`export` by default)
+/// `git-pages`, which pushes to the branch set in each site's driftwood.toml
+/// `s3`, which uploads to the bucket set in each site's driftwood.toml
+/// `sftp`, which uploads to the server set in each site's driftwood.toml
+/// Netlify needs a saved token or NETLIFY_AUTH_TOKEN, without one this asks the user to log in
+fn hosting_provider() -> Result<Box<dyn HostingProvider>> {
+    Ok(match env::var("DRIFTWOOD_PROVIDER").as_deref() {
+        Ok("export") => {
+            let destination =
+                env::var("DRIFTWOOD_EXPORT_PATH").unwrap_or_else(|_| "export".to_string());
+            Box::new(ExportTarget::new(destination))
         }
-        Err(e) => {
-            println!("Failed to convert markdown to HTML.");
-            println!("Error: {:?}", e);
-        }
-    }
-    println!("Press enter to return to the main menu.");
-    print!("> ");
-    std::io::stdin()
-        .read_line(&mut input)
-        .context("Failed to read line")?;
-
-    Ok(())
+        Ok("git-pages") => Box::new(GitPagesTarget::new()),
+        Ok("s3") => Box::new(S3Target::new()),
+        Ok("sftp") => Box::new(SftpTarget::new()),
+        _ => Box::new(Netlify::connect()?),
+    })
 }
 
 /// Add a new site
-/// netlify: A Netlify instance
+/// provider: The hosting provider to create the site with
 /// site_name: The name of the site to create
-/// Returns a vector of SiteDetails
-fn create_site(
-    netlify: Netlify,
-    site_name: String,
-) -> Result<SiteDetails, Box<dyn std::error::Error>> {
-    match netlify.create_site(SiteDetails {
-        name: Some(site_name),
-        id: None,
-        url: None,
-        ssl: None,
-        screenshot_url: None,
-        required: None,
-    }) {
-        Ok(sites) => {
+/// Returns the new site's SiteDetails
+fn create_site(provider: &dyn HostingProvider, site_name: String) -> Result<SiteDetails> {
+    match provider.create_site(&site_name) {
+        Ok(site) => {
             println!("> Site Details:");
-            println!("> {:?}", sites);
-            Ok(sites)
+            println!("> {:?}", site);
+            Ok(site)
         }
         Err(e) => {
             println!("> Error: {:?}", e);
@@ -645,10 +1033,10 @@ fn create_site(
 }
 
 /// Get all the sites for the user
-/// netlify: A Netlify instance
+/// provider: The hosting provider to list the sites of
 /// Returns a vector of SiteDetails
-fn get_sites(netlify: Netlify) -> Vec<SiteDetails> {
-    match netlify.get_sites() {
+fn get_sites(provider: &dyn HostingProvider) -> Vec<SiteDetails> {
+    match provider.list_sites() {
         Ok(sites) => {
             println!("Done");
             for each in &sites {
@@ -665,14 +1053,14 @@ fn get_sites(netlify: Netlify) -> Vec<SiteDetails> {
 }
 
 /// Update the site details
-/// netlify: A Netlify instance
-/// Returns a vector of the new SiteDetails
+/// provider: The hosting provider the site is on
+/// Returns the new SiteDetails
 fn update_site_details(
-    netlify: Netlify,
-    existing_site_details: SiteDetails,
+    provider: &dyn HostingProvider,
+    existing_site_details: &SiteDetails,
     new_site_details: SiteDetails,
-) -> Result<SiteDetails, Box<dyn std::error::Error>> {
-    match netlify.update_site(existing_site_details, new_site_details) {
+) -> Result<SiteDetails> {
+    match provider.update_site(existing_site_details, new_site_details) {
         Ok(site) => {
             println!(">Site Details:");
             println!("{:?}", site);
@@ -686,19 +1074,252 @@ fn update_site_details(
 }
 
 fn provision_ssl(
-    netlify: Netlify,
-    site: SiteDetails,
-    ssl_details: Ssl_Cert,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    match netlify.provision_ssl(site, ssl_details) {
-        Ok(ssl_enabled) => {
-            println!("> SSL Status:");
-            println!("{:?}", ssl_enabled);
-            Ok(ssl_enabled)
+    provider: &dyn HostingProvider,
+    site: &SiteDetails,
+    ssl_details: SslCert,
+) -> Result<()> {
+    match provider.provision_ssl(site, ssl_details) {
+        Ok(()) => {
+            println!("> SSL certificate provisioned.");
+            Ok(())
         }
         Err(e) => {
             println!("Error: {:?}", e);
             Err(e)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use driftwood::hosting::{new_local_site, DeployInfo};
+
+    /// A provider that only knows a list of deploys
+    struct FakeProvider {
+        deploys: Vec<DeployInfo>,
+    }
+
+    impl HostingProvider for FakeProvider {
+        fn name(&self) -> &str {
+            "Fake"
+        }
+
+        fn create_site(&self, _name: &str) -> Result<SiteDetails> {
+            unimplemented!()
+        }
+
+        fn list_sites(&self) -> Result<Vec<SiteDetails>> {
+            unimplemented!()
+        }
+
+        fn update_site(&self, _site: &SiteDetails, _new: SiteDetails) -> Result<SiteDetails> {
+            unimplemented!()
+        }
+
+        fn delete_site(&self, _site: &SiteDetails) -> Result<()> {
+            unimplemented!()
+        }
+
+        fn deploy(&self, _site: &SiteDetails, _output_path: &Path) -> Result<Deployment> {
+            unimplemented!()
+        }
+
+        fn list_deploys(&self, _site: &SiteDetails) -> Result<Vec<DeployInfo>> {
+            Ok(self.deploys.clone())
+        }
+    }
+
+    /// A deploy in the given state, `live` marks the one the site serves
+    fn deploy(id: &str, state: &str, draft: bool, live: bool) -> DeployInfo {
+        DeployInfo {
+            id: id.to_string(),
+            state: Some(state.to_string()),
+            draft,
+            live,
+            ..DeployInfo::default()
+        }
+    }
+
+    fn provider(deploys: Vec<DeployInfo>) -> FakeProvider {
+        FakeProvider { deploys }
+    }
+
+    #[test]
+    fn rolls_back_to_the_deploy_before_the_live_one() {
+        let provider = provider(vec![
+            deploy("newest", "ready", false, true),
+            deploy("preview", "ready", true, false),
+            deploy("broken", "error", false, false),
+            deploy("older", "ready", false, false),
+        ]);
+        assert_eq!(
+            previous_deploy(&provider, &new_local_site("blog")).unwrap(),
+            "older"
+        );
+    }
+
+    #[test]
+    fn rolls_back_from_an_earlier_rollback() {
+        // after rolling back to `middle`, the newest deploy isn't the live one
+        let provider = provider(vec![
+            deploy("newest", "ready", false, false),
+            deploy("middle", "ready", false, true),
+            deploy("oldest", "ready", false, false),
+        ]);
+        assert_eq!(
+            previous_deploy(&provider, &new_local_site("blog")).unwrap(),
+            "oldest"
+        );
+    }
+
+    #[test]
+    fn needs_a_live_deploy_and_an_older_one() {
+        let site = new_local_site("blog");
+        assert!(previous_deploy(&provider(vec![]), &site).is_err());
+        assert!(previous_deploy(
+            &provider(vec![deploy("only", "ready", false, false)]),
+            &site
+        )
+        .is_err());
+        assert!(previous_deploy(
+            &provider(vec![
+                deploy("newest", "ready", false, false),
+                deploy("oldest", "ready", false, true),
+            ]),
+            &site
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn promotes_the_newest_ready_preview() {
+        let site = new_local_site("blog");
+        let previews = provider(vec![
+            deploy("live", "ready", false, true),
+            deploy("building", "building", true, false),
+            deploy("newer", "ready", true, false),
+            deploy("older", "ready", true, false),
+        ]);
+        assert_eq!(newest_preview(&previews, &site).unwrap(), "newer");
+
+        let no_preview = provider(vec![
+            deploy("live", "ready", false, true),
+            deploy("failed", "error", true, false),
+        ]);
+        assert!(newest_preview(&no_preview, &site).is_err());
+    }
+
+    #[test]
+    fn a_promoted_or_restored_deploy_is_recorded_with_its_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let history_file = dir.path().join(driftwood::history::HISTORY_FILE);
+        append_history(
+            &history_file,
+            DeployRecord::draft("Fake", Some("preview".to_string()), None).with_commit(Some(
+                driftwood::history::SiteCommit {
+                    id: "1234567".to_string(),
+                    message: "Add a post".to_string(),
+                },
+            )),
+        )
+        .unwrap();
+
+        let deployment = Deployment {
+            id: Some("preview".to_string()),
+            url: Some("https://blog.example.com".to_string()),
+        };
+        append_restore("Fake", &history_file, "preview", deployment).unwrap();
+
+        let history = read_history(&history_file).unwrap();
+        assert_eq!(history.len(), 2);
+        let promoted = &history[1];
+        assert_eq!(promoted.state, "ready");
+        assert_eq!(promoted.id.as_deref(), Some("preview"));
+        assert_eq!(promoted.url.as_deref(), Some("https://blog.example.com"));
+        assert_eq!(promoted.commit.as_deref(), Some("1234567"));
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn no_stdin() -> Result<String> {
+        panic!("read stdin without --with-token")
+    }
+
+    #[test]
+    fn login_reads_a_trimmed_token_from_stdin() {
+        let source = login_source(&args(&["--with-token"]), None, || {
+            Ok("  nfp_abc\n".to_string())
+        })
+        .unwrap();
+        assert_eq!(source, Some(LoginSource::Token("nfp_abc".to_string())));
+
+        // the token given on stdin wins over the environment
+        let source = login_source(
+            &args(&["--with-token"]),
+            Some("nfp_env".to_string()),
+            || Ok("nfp_stdin".to_string()),
+        )
+        .unwrap();
+        assert_eq!(source, Some(LoginSource::Token("nfp_stdin".to_string())));
+    }
+
+    #[test]
+    fn login_reads_a_trimmed_token_from_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "nfp_abc\n\n").unwrap();
+
+        let source = login_source(
+            &args(&["--token-file", path.to_str().unwrap()]),
+            None,
+            no_stdin,
+        )
+        .unwrap();
+        assert_eq!(source, Some(LoginSource::Token("nfp_abc".to_string())));
+
+        let missing = dir.path().join("missing");
+        assert!(login_source(
+            &args(&["--token-file", missing.to_str().unwrap()]),
+            None,
+            no_stdin
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn login_uses_the_env_token_and_only_falls_back_to_the_browser_without_one() {
+        let source = login_source(&[], Some(" nfp_env ".to_string()), no_stdin).unwrap();
+        assert_eq!(source, Some(LoginSource::Token("nfp_env".to_string())));
+
+        let source = login_source(&[], None, no_stdin).unwrap();
+        assert_eq!(source, Some(LoginSource::Browser));
+    }
+
+    #[test]
+    fn login_rejects_empty_tokens_and_unknown_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, " \n").unwrap();
+
+        assert!(login_source(&args(&["--with-token"]), None, || Ok("\n".to_string())).is_err());
+        assert!(login_source(&[], Some(String::new()), no_stdin).is_err());
+        assert!(login_source(
+            &args(&["--token-file", path.to_str().unwrap()]),
+            None,
+            no_stdin
+        )
+        .is_err());
+
+        assert_eq!(
+            login_source(&args(&["--token-file"]), None, no_stdin).unwrap(),
+            None
+        );
+        assert_eq!(
+            login_source(&args(&["--browser"]), None, no_stdin).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,197 @@
+/// Config Module
+/// Optional per-site settings, read from `driftwood.toml` in the site directory.
+/// Every setting has a default, so a site without the file builds as before.
+use crate::SiteDetails;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Name of the config file inside the site directory
+pub const CONFIG_FILE: &str = "driftwood.toml";
+
+/// SiteConfig struct
+/// Per-site settings
+/// base_url: The public URL of the site for feeds and the sitemap, overrides the URL the host reports
+/// feed_limit: The maximum number of posts in the RSS, Atom and JSON feeds
+/// page_size: The number of posts per page on the home and tag pages, 0 shows them all on one page
+/// git_pages: Where to push the site when deploying to a `gh-pages` style branch
+/// s3: Where to upload the site when deploying to an S3-compatible bucket
+/// sftp: Where to upload the site when deploying over SFTP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SiteConfig {
+    pub base_url: Option<String>,
+    pub feed_limit: usize,
+    pub page_size: usize,
+    pub git_pages: Option<GitPagesConfig>,
+    pub s3: Option<S3Config>,
+    pub sftp: Option<SftpConfig>,
+}
+
+/// GitPagesConfig struct
+/// The `[git_pages]` section of the config file
+/// remote: The name of a remote of the site's repository, or a URL or path to push to
+/// branch: The branch the built site is committed to
+/// cname: The custom domain written to the `CNAME` file, if any
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitPagesConfig {
+    pub remote: String,
+    #[serde(default = "GitPagesConfig::default_branch")]
+    pub branch: String,
+    pub cname: Option<String>,
+}
+
+impl GitPagesConfig {
+    fn default_branch() -> String {
+        "gh-pages".to_string()
+    }
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        SiteConfig {
+            base_url: None,
+            feed_limit: 20,
+            page_size: 10,
+            git_pages: None,
+            s3: None,
+            sftp: None,
+        }
+    }
+}
+
+impl SiteConfig {
+    /// Load a site's config file, or the defaults if it has none
+    pub fn load(site: &SiteDetails) -> Result<SiteConfig> {
+        let config_path = site.build_site_path()?.join(CONFIG_FILE);
+        if !config_path.exists() {
+            return Ok(SiteConfig::default());
+        }
+
+        let config = fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read {}", config_path.display()))?;
+        toml::from_str(&config)
+            .with_context(|| format!("Failed to parse {}", config_path.display()))
+    }
+
+    /// The base URL of the site without a trailing slash
+    /// Falls back to the URL the host reports for the site, over HTTPS when the site has SSL
+    pub fn resolve_base_url(&self, site: &SiteDetails) -> Option<String> {
+        self.base_url
+            .clone()
+            .or_else(|| {
+                let url = site.url.clone()?;
+                match url.strip_prefix("http://") {
+                    Some(rest) if site.ssl == Some(true) => Some(format!("https://{}", rest)),
+                    _ => Some(url),
+                }
+            })
+            .map(|url| url.trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+    }
+}
+
+/// S3Config struct
+/// The `[s3]` section of the config file
+/// The access keys are read from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, never from this file
+/// endpoint: The URL of the S3-compatible service, e.g. `https://s3.eu-west-1.amazonaws.com`
+/// bucket: The bucket the site is uploaded to
+/// prefix: The folder inside the bucket the site is uploaded to, empty for the bucket root
+/// region: The region used to sign requests
+/// path_style: Address the bucket as `endpoint/bucket` instead of `bucket.endpoint`, MinIO needs this
+/// html_cache_control: The Cache-Control header for pages and feeds
+/// asset_cache_control: The Cache-Control header for every other file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "S3Config::default_region")]
+    pub region: String,
+    #[serde(default = "S3Config::default_path_style")]
+    pub path_style: bool,
+    #[serde(default = "S3Config::default_html_cache_control")]
+    pub html_cache_control: String,
+    #[serde(default = "S3Config::default_asset_cache_control")]
+    pub asset_cache_control: String,
+}
+
+impl S3Config {
+    fn default_region() -> String {
+        "us-east-1".to_string()
+    }
+
+    fn default_path_style() -> bool {
+        true
+    }
+
+    fn default_html_cache_control() -> String {
+        "public, max-age=0, must-revalidate".to_string()
+    }
+
+    fn default_asset_cache_control() -> String {
+        "public, max-age=86400".to_string()
+    }
+}
+
+/// SftpConfig struct
+/// The `[sftp]` section of the config file
+/// A password can be given in DRIFTWOOD_SFTP_PASSWORD, it's never read from this file
+/// host: The server to connect to
+/// port: The SSH port of the server
+/// username: The user to log in as
+/// remote_path: The directory the site is served from
+/// private_key: A private key to log in with, the SSH agent is used when it's not set
+/// known_hosts: The known_hosts file the server's key is checked against, `~/.ssh/known_hosts` by default
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SftpConfig {
+    pub host: String,
+    #[serde(default = "SftpConfig::default_port")]
+    pub port: u16,
+    pub username: String,
+    pub remote_path: String,
+    pub private_key: Option<String>,
+    pub known_hosts: Option<String>,
+}
+
+impl SftpConfig {
+    fn default_port() -> u16 {
+        22
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(url: &str, ssl: Option<bool>) -> SiteDetails {
+        SiteDetails {
+            name: Some("blog".to_string()),
+            id: Some("1".to_string()),
+            ssl,
+            url: Some(url.to_string()),
+            screenshot_url: None,
+        }
+    }
+
+    #[test]
+    fn base_url_prefers_https_when_the_site_has_ssl() {
+        let config: SiteConfig = toml::from_str("").unwrap();
+        assert_eq!(
+            config.resolve_base_url(&site("http://blog.netlify.app/", Some(true))),
+            Some("https://blog.netlify.app".to_string())
+        );
+        assert_eq!(
+            config.resolve_base_url(&site("http://blog.example.com", Some(false))),
+            Some("http://blog.example.com".to_string())
+        );
+
+        let config: SiteConfig =
+            toml::from_str("base_url = \"https://example.com/blog/\"").unwrap();
+        assert_eq!(
+            config.resolve_base_url(&site("http://blog.netlify.app", Some(true))),
+            Some("https://example.com/blog".to_string())
+        );
+    }
+}
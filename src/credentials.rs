@@ -0,0 +1,458 @@
+/// Credentials Module
+/// Where the app keeps secrets, like the Netlify access token.
+/// The OS secret service is used when there is one, otherwise a file in the user's config
+/// directory that only they can read, encrypted with a key derived from a passphrase.
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use rand::{rngs::OsRng, RngCore};
+use secret_service::{
+    blocking::{Collection, SecretService},
+    EncryptionType,
+};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, OpenOptions},
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Name of the app's directory inside the user's config directory
+const CONFIG_DIR: &str = "driftwood";
+/// Name of the credentials file inside the app's config directory
+const CREDENTIALS_FILE: &str = "credentials";
+/// Name of the key file older versions kept beside the credentials file
+const LEGACY_KEY_FILE: &str = "credentials.key";
+/// The passphrase for the credentials file, asked for when it isn't set
+const PASSPHRASE_VAR: &str = "DRIFTWOOD_CREDENTIALS_PASSPHRASE";
+/// Length of the random salt the key is derived with, written first in the file
+const SALT_LEN: usize = 16;
+/// Length of the random nonce written after the salt
+const NONCE_LEN: usize = 12;
+/// PBKDF2-HMAC-SHA256 rounds used to derive the key from the passphrase
+const KDF_ROUNDS: u32 = 600_000;
+/// The `service` attribute of every secret the app saves in the secret service
+const SERVICE: &str = "driftwood";
+
+/// CredentialStore trait
+/// Somewhere secrets can be saved, read back and removed, each under a key like `netlify`
+pub trait CredentialStore: Send + Sync {
+    /// The name of the store, to tell the user where their login is kept
+    fn name(&self) -> &str;
+
+    /// Read a secret, None if nothing is saved under the key
+    fn get(&self, key: &str) -> Result<Option<String>>;
+
+    /// Save a secret, replacing whatever was saved under the key
+    fn set(&self, key: &str, secret: &str) -> Result<()>;
+
+    /// Remove a secret, removing one that isn't there is not an error
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// The store to keep credentials in, from DRIFTWOOD_CREDENTIAL_STORE:
+/// `file`, the credentials file in the config directory
+/// `secret-service`, the OS secret service, failing if there isn't one
+/// anything else, the secret service when it's available and the file when it's not
+pub fn default_store() -> Result<Box<dyn CredentialStore>> {
+    match env::var("DRIFTWOOD_CREDENTIAL_STORE").as_deref() {
+        Ok("file") => Ok(Box::new(FileStore::new()?)),
+        Ok("secret-service") => Ok(Box::new(SecretServiceStore::connect()?)),
+        _ => match SecretServiceStore::connect() {
+            Ok(store) => Ok(Box::new(store)),
+            Err(e) => {
+                println!("> {:#}, using the encrypted credentials file", e);
+                Ok(Box::new(FileStore::new()?))
+            }
+        },
+    }
+}
+
+/// FileStore struct
+/// Keeps every secret in one AES-256-GCM encrypted file readable only by the user.
+/// The key is derived from a passphrase with PBKDF2, nothing that can decrypt the file is stored.
+/// The file is the salt, then the nonce, then the encrypted secrets.
+/// dir: The app's config directory, DRIFTWOOD_CONFIG_DIR or `driftwood` in the user's config directory
+/// passphrase: The passphrase, once it has been read
+/// rounds: The number of PBKDF2 rounds
+pub struct FileStore {
+    dir: PathBuf,
+    passphrase: Mutex<Option<String>>,
+    rounds: u32,
+}
+
+impl FileStore {
+    /// Use the credentials file in the app's config directory, it's created on the first save
+    /// The passphrase is read from DRIFTWOOD_CREDENTIALS_PASSPHRASE, or asked for, when the file is first used
+    pub fn new() -> Result<FileStore> {
+        let dir = match env::var("DRIFTWOOD_CONFIG_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => dirs::config_dir()
+                .context("Couldn't find the user's config directory")?
+                .join(CONFIG_DIR),
+        };
+        Ok(FileStore {
+            dir,
+            passphrase: Mutex::new(None),
+            rounds: KDF_ROUNDS,
+        })
+    }
+
+    /// Path of the credentials file
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(CREDENTIALS_FILE)
+    }
+
+    /// Read and decrypt every saved secret
+    fn read_all(&self) -> Result<HashMap<String, String>> {
+        let path = self.path();
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let contents =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let legacy_key_path = self.dir.join(LEGACY_KEY_FILE);
+        if legacy_key_path.exists() {
+            return self.migrate_legacy(&contents, &legacy_key_path);
+        }
+
+        if contents.len() < SALT_LEN + NONCE_LEN {
+            bail!("{} is corrupted", path.display());
+        }
+        let (salt, rest) = contents.split_at(SALT_LEN);
+        let (nonce, encrypted) = rest.split_at(NONCE_LEN);
+        let decrypted = self
+            .cipher(salt)?
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| {
+                anyhow!(
+                    "Failed to decrypt {}, the passphrase is wrong or the file was changed",
+                    path.display()
+                )
+            })?;
+        serde_json::from_slice(&decrypted)
+            .with_context(|| format!("Failed to read the secrets in {}", path.display()))
+    }
+
+    /// Encrypt and write every secret with a fresh salt and nonce, replacing the file
+    fn write_all(&self, secrets: &HashMap<String, String>) -> Result<()> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let encrypted = self
+            .cipher(&salt)?
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                serde_json::to_vec(secrets)?.as_slice(),
+            )
+            .map_err(|_| anyhow!("Failed to encrypt the credentials"))?;
+        write_private(
+            &self.path(),
+            &[salt.as_slice(), nonce.as_slice(), &encrypted].concat(),
+        )
+    }
+
+    /// The cipher for the credentials file, with the key derived from the passphrase and salt
+    fn cipher(&self, salt: &[u8]) -> Result<Aes256Gcm> {
+        let passphrase = self.passphrase()?;
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, self.rounds, &mut key);
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    }
+
+    /// The passphrase of the credentials file, from DRIFTWOOD_CREDENTIALS_PASSPHRASE or the terminal
+    /// It's only asked for once per run
+    fn passphrase(&self) -> Result<String> {
+        let mut passphrase = self.passphrase.lock().unwrap();
+        if let Some(passphrase) = passphrase.as_ref() {
+            return Ok(passphrase.clone());
+        }
+
+        let read = match env::var(PASSPHRASE_VAR) {
+            Ok(read) => read,
+            Err(_) if std::io::stdin().is_terminal() => {
+                let prompt = if self.path().exists() {
+                    "Passphrase for the credentials file: "
+                } else {
+                    "Choose a passphrase for the credentials file: "
+                };
+                rpassword::prompt_password(prompt).context("Failed to read the passphrase")?
+            }
+            Err(_) => bail!(
+                "There's no terminal to ask for the credentials file's passphrase, set {}",
+                PASSPHRASE_VAR
+            ),
+        };
+        if read.is_empty() {
+            bail!("The credentials file's passphrase can't be empty");
+        }
+        *passphrase = Some(read.clone());
+        Ok(read)
+    }
+
+    /// Read a file written by an older version, encrypted with a key kept beside it,
+    /// then write it again with the passphrase and remove the key
+    fn migrate_legacy(&self, contents: &[u8], key_path: &Path) -> Result<HashMap<String, String>> {
+        let key =
+            fs::read(key_path).with_context(|| format!("Failed to read {}", key_path.display()))?;
+        if key.len() != 32 || contents.len() < NONCE_LEN {
+            bail!("{} is corrupted", self.path().display());
+        }
+        let (nonce, encrypted) = contents.split_at(NONCE_LEN);
+        let decrypted = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| anyhow!("Failed to decrypt {}", self.path().display()))?;
+        let secrets: HashMap<String, String> = serde_json::from_slice(&decrypted)
+            .with_context(|| format!("Failed to read the secrets in {}", self.path().display()))?;
+
+        println!("> Encrypting the credentials file with a passphrase");
+        self.write_all(&secrets)?;
+        fs::remove_file(key_path)
+            .with_context(|| format!("Failed to remove {}", key_path.display()))?;
+        Ok(secrets)
+    }
+}
+
+impl CredentialStore for FileStore {
+    fn name(&self) -> &str {
+        "credentials file"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.read_all()?.remove(key))
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<()> {
+        let mut secrets = self.read_all()?;
+        secrets.insert(key.to_string(), secret.to_string());
+        self.write_all(&secrets)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let mut secrets = self.read_all()?;
+        if secrets.remove(key).is_some() {
+            self.write_all(&secrets)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a file only the user can read, creating its directory if needed
+/// path: The file to write, it's replaced if it exists
+/// contents: What to write
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    // the mode only applies to new files, so tighten one left by something else too
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// SecretServiceStore struct
+/// Keeps secrets in the OS keyring through the freedesktop Secret Service D-Bus API,
+/// e.g. GNOME Keyring or KWallet. A new connection is made for every call.
+pub struct SecretServiceStore;
+
+impl SecretServiceStore {
+    /// Check the secret service is running and its keyring can be opened
+    pub fn connect() -> Result<SecretServiceStore> {
+        SecretServiceStore::with_collection(|_| Ok(()))?;
+        Ok(SecretServiceStore)
+    }
+
+    /// Run a call against the default keyring, unlocking it first
+    fn with_collection<T>(
+        call: impl FnOnce(&Collection) -> Result<T, secret_service::Error>,
+    ) -> Result<T> {
+        let service = SecretService::connect(EncryptionType::Dh)
+            .context("The secret service is unavailable")?;
+        let collection = service
+            .get_default_collection()
+            .or_else(|_| service.get_any_collection())
+            .context("The secret service has no keyring")?;
+        collection
+            .ensure_unlocked()
+            .context("Failed to unlock the keyring")?;
+        call(&collection).context("The secret service failed")
+    }
+
+    /// The attributes a secret is saved and looked up by
+    fn attributes(key: &str) -> HashMap<&str, &str> {
+        HashMap::from([("service", SERVICE), ("account", key)])
+    }
+}
+
+impl CredentialStore for SecretServiceStore {
+    fn name(&self) -> &str {
+        "secret service"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let secret = SecretServiceStore::with_collection(|collection| {
+            match collection
+                .search_items(SecretServiceStore::attributes(key))?
+                .first()
+            {
+                Some(item) => item.get_secret().map(Some),
+                None => Ok(None),
+            }
+        })?;
+        secret
+            .map(|secret| String::from_utf8(secret).context("The saved secret isn't text"))
+            .transpose()
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<()> {
+        SecretServiceStore::with_collection(|collection| {
+            collection
+                .create_item(
+                    &format!("Driftwood {}", key),
+                    SecretServiceStore::attributes(key),
+                    secret.as_bytes(),
+                    true,
+                    "text/plain",
+                )
+                .map(|_| ())
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        SecretServiceStore::with_collection(|collection| {
+            for item in collection.search_items(SecretServiceStore::attributes(key))? {
+                item.delete()?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(dir: &Path, passphrase: &str) -> FileStore {
+        FileStore {
+            dir: dir.to_path_buf(),
+            passphrase: Mutex::new(Some(passphrase.to_string())),
+            rounds: 1,
+        }
+    }
+
+    #[test]
+    fn secrets_round_trip_through_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), "hunter2");
+        assert_eq!(store.get("netlify").unwrap(), None);
+
+        store.set("netlify", "token").unwrap();
+        store.set("other", "secret").unwrap();
+        assert_eq!(store.get("netlify").unwrap().as_deref(), Some("token"));
+
+        let reopened = self::store(dir.path(), "hunter2");
+        assert_eq!(reopened.get("other").unwrap().as_deref(), Some("secret"));
+        assert!(!fs::read(store.path())
+            .unwrap()
+            .windows(6)
+            .any(|w| w == b"secret"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_the_user_can_read_the_file_and_no_key_is_written() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), "hunter2");
+        store.set("netlify", "token").unwrap();
+
+        let mode = fs::metadata(store.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn a_changed_file_or_wrong_passphrase_fails_to_decrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), "hunter2");
+        store.set("netlify", "token").unwrap();
+
+        let wrong = self::store(dir.path(), "hunter3");
+        assert!(wrong.get("netlify").is_err());
+
+        let mut contents = fs::read(store.path()).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        fs::write(store.path(), contents).unwrap();
+        let error = store.get("netlify").unwrap_err().to_string();
+        assert!(error.contains("Failed to decrypt"), "{}", error);
+    }
+
+    #[test]
+    fn delete_removes_only_that_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), "hunter2");
+        store.set("netlify", "token").unwrap();
+        store.set("other", "secret").unwrap();
+
+        store.delete("netlify").unwrap();
+        store.delete("missing").unwrap();
+        assert_eq!(store.get("netlify").unwrap(), None);
+        assert_eq!(store.get("other").unwrap().as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn a_file_with_its_key_beside_it_is_moved_to_the_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = [7u8; 32];
+        let nonce = [1u8; NONCE_LEN];
+        let encrypted = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                br#"{"netlify":"token"}"#.as_slice(),
+            )
+            .unwrap();
+        fs::write(dir.path().join(LEGACY_KEY_FILE), key).unwrap();
+        fs::write(
+            dir.path().join(CREDENTIALS_FILE),
+            [&nonce[..], &encrypted].concat(),
+        )
+        .unwrap();
+
+        let store = store(dir.path(), "hunter2");
+        assert_eq!(store.get("netlify").unwrap().as_deref(), Some("token"));
+        assert!(!dir.path().join(LEGACY_KEY_FILE).exists());
+        assert_eq!(
+            self::store(dir.path(), "hunter2")
+                .get("netlify")
+                .unwrap()
+                .as_deref(),
+            Some("token")
+        );
+    }
+}
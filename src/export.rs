@@ -0,0 +1,479 @@
+/// Export Module
+/// A hosting provider that writes the built site into a local directory or a `.zip` archive,
+/// so it can be hosted anywhere. It never logs in or touches the network.
+use crate::hosting::{
+    list_local_sites, new_local_site, rename_local_site, Deployment, HostingProvider,
+};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::SiteDetails;
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+/// ExportFormat enum
+/// Directory: Copy the files into a directory
+/// Zip: Write the files into a `.zip` archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Directory,
+    Zip,
+}
+
+/// ExportTarget struct
+/// destination: The directory or `.zip` file the site is written to
+/// format: How the site is written, picked from the destination's extension
+pub struct ExportTarget {
+    destination: PathBuf,
+    format: ExportFormat,
+}
+
+impl ExportTarget {
+    /// Create an export target
+    /// A destination ending in `.zip` is written as an archive, anything else as a directory
+    pub fn new(destination: impl Into<PathBuf>) -> ExportTarget {
+        let destination = destination.into();
+        let format = match destination.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("zip") => ExportFormat::Zip,
+            _ => ExportFormat::Directory,
+        };
+        ExportTarget {
+            destination,
+            format,
+        }
+    }
+
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Export a built site
+    /// output_path: The directory the site was built into
+    /// Returns the manifest of the exported files
+    pub fn export(&self, output_path: &Path) -> Result<Manifest> {
+        println!(
+            ">> Exporting {} to {}",
+            output_path.display(),
+            self.destination.display()
+        );
+
+        if !output_path.join("index.html").exists() {
+            bail!("index.html not found in {}", output_path.display());
+        }
+
+        let manifest = Manifest::build(output_path)?;
+        let manifest_json = manifest.to_json()?;
+
+        // write next to the destination first, so a failed export leaves the previous one alone
+        let staging_path = self.staging_path()?;
+        let result = match self.format {
+            ExportFormat::Directory => {
+                write_directory(&staging_path, output_path, &manifest, &manifest_json)
+            }
+            ExportFormat::Zip => write_zip(&staging_path, output_path, &manifest, &manifest_json),
+        };
+        if let Err(e) = result {
+            remove_path(&staging_path)?;
+            return Err(e);
+        }
+
+        if let Err(e) = self.remove_previous_export() {
+            remove_path(&staging_path)?;
+            return Err(e);
+        }
+        fs::rename(&staging_path, &self.destination)
+            .context("Failed to move the export into place")?;
+
+        println!(">> Exported {} files", manifest.files.len());
+        Ok(manifest)
+    }
+
+    /// Make way for a new export
+    /// Only an empty directory or an earlier export is removed, anything else at the
+    /// destination is the user's and is left alone with an error
+    fn remove_previous_export(&self) -> Result<()> {
+        let destination = &self.destination;
+        if !destination.exists() {
+            return Ok(());
+        }
+
+        match self.format {
+            ExportFormat::Directory => {
+                if !destination.is_dir() {
+                    bail!("{} exists and isn't a directory", destination.display());
+                }
+                if fs::read_dir(destination)?.next().is_none() {
+                    return fs::remove_dir(destination)
+                        .with_context(|| format!("Failed to remove {}", destination.display()));
+                }
+                let manifest = read_export_manifest(destination)?;
+                let extra = unlisted_files(destination, &manifest)?;
+                if let Some(extra) = extra.first() {
+                    bail!(
+                        "{} holds files that weren't exported, e.g. {}, export somewhere else",
+                        destination.display(),
+                        extra
+                    );
+                }
+                fs::remove_dir_all(destination)
+                    .with_context(|| format!("Failed to remove {}", destination.display()))
+            }
+            ExportFormat::Zip => {
+                if !is_export_zip(destination) {
+                    bail!(
+                        "{} exists and isn't an earlier export, export somewhere else",
+                        destination.display()
+                    );
+                }
+                fs::remove_file(destination)
+                    .with_context(|| format!("Failed to remove {}", destination.display()))
+            }
+        }
+    }
+
+    /// The path the export is written to before it's moved into place, e.g. `.site.zip.tmp`
+    fn staging_path(&self) -> Result<PathBuf> {
+        let name = self
+            .destination
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Invalid export destination: {}", self.destination.display()))?;
+        Ok(self
+            .destination
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(format!(".{}.tmp", name)))
+    }
+}
+
+impl HostingProvider for ExportTarget {
+    fn name(&self) -> &str {
+        "Local export"
+    }
+
+    fn create_site(&self, name: &str) -> Result<SiteDetails> {
+        Ok(new_local_site(name))
+    }
+
+    fn list_sites(&self) -> Result<Vec<SiteDetails>> {
+        list_local_sites()
+    }
+
+    fn update_site(&self, site: &SiteDetails, new_details: SiteDetails) -> Result<SiteDetails> {
+        rename_local_site(site, new_details)
+    }
+
+    /// Removes the exported copy, the site's sources are kept
+    /// Only the files listed in the export's manifest are removed from a directory
+    fn delete_site(&self, _site: &SiteDetails) -> Result<()> {
+        let destination = &self.destination;
+        if !destination.exists() {
+            return Ok(());
+        }
+
+        match self.format {
+            ExportFormat::Directory => {
+                let manifest = read_export_manifest(destination)?;
+                for url_path in manifest.files.keys() {
+                    let path = Manifest::local_path(destination, url_path);
+                    if path.is_file() {
+                        fs::remove_file(&path)
+                            .with_context(|| format!("Failed to remove {}", path.display()))?;
+                    }
+                }
+                fs::remove_file(destination.join(MANIFEST_FILE))
+                    .context("Failed to remove the manifest")?;
+                prune_dirs(destination, manifest.files.keys());
+                Ok(())
+            }
+            ExportFormat::Zip => {
+                if !is_export_zip(destination) {
+                    bail!("{} isn't an export", destination.display());
+                }
+                fs::remove_file(destination).context("Failed to remove the export")
+            }
+        }
+    }
+
+    fn deploy(&self, _site: &SiteDetails, output_path: &Path) -> Result<Deployment> {
+        self.export(output_path)?;
+        Ok(Deployment {
+            id: None,
+            url: Some(self.destination.display().to_string()),
+        })
+    }
+}
+
+/// Copy the files and the manifest into a directory
+/// output_path: The directory the site was built into
+fn write_directory(
+    dir: &Path,
+    output_path: &Path,
+    manifest: &Manifest,
+    manifest_json: &str,
+) -> Result<()> {
+    for url_path in manifest.files.keys() {
+        let source = Manifest::local_path(output_path, url_path);
+        let target = Manifest::local_path(dir, url_path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::copy(&source, &target)
+            .with_context(|| format!("Failed to copy {}", source.display()))?;
+    }
+    fs::write(dir.join(MANIFEST_FILE), manifest_json).context("Failed to write the manifest")?;
+    Ok(())
+}
+
+/// Write the files and the manifest into a `.zip` archive
+/// output_path: The directory the site was built into
+fn write_zip(
+    zip_path: &Path,
+    output_path: &Path,
+    manifest: &Manifest,
+    manifest_json: &str,
+) -> Result<()> {
+    let zip_file = File::create(zip_path)
+        .with_context(|| format!("Failed to create {}", zip_path.display()))?;
+    let mut zip = ZipWriter::new(zip_file);
+    let options = SimpleFileOptions::default();
+
+    for url_path in manifest.files.keys() {
+        let source = Manifest::local_path(output_path, url_path);
+        zip.start_file(url_path.trim_start_matches('/'), options)?;
+        zip.write_all(
+            &fs::read(&source).with_context(|| format!("Failed to read {}", source.display()))?,
+        )?;
+    }
+    zip.start_file(MANIFEST_FILE, options)?;
+    zip.write_all(manifest_json.as_bytes())?;
+
+    zip.finish().context("Failed to finish the archive")?;
+    Ok(())
+}
+
+/// Read the manifest an earlier export left in a directory
+fn read_export_manifest(dir: &Path) -> Result<Manifest> {
+    let path = dir.join(MANIFEST_FILE);
+    if !path.is_file() {
+        bail!(
+            "{} has no {}, it isn't an export",
+            dir.display(),
+            MANIFEST_FILE
+        );
+    }
+    Manifest::from_json(
+        &fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?,
+    )
+}
+
+/// The files in a directory that aren't in its manifest, in `/path` form
+fn unlisted_files(root: &Path, manifest: &Manifest) -> Result<Vec<String>> {
+    let mut unlisted = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let url_path = path
+                .strip_prefix(root)?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let url_path = format!("/{}", url_path);
+            if url_path != format!("/{}", MANIFEST_FILE) && !manifest.files.contains_key(&url_path)
+            {
+                unlisted.push(url_path);
+            }
+        }
+    }
+    unlisted.sort();
+    Ok(unlisted)
+}
+
+/// Whether a file is a `.zip` archive written by an earlier export
+fn is_export_zip(path: &Path) -> bool {
+    File::open(path)
+        .ok()
+        .and_then(|file| ZipArchive::new(file).ok())
+        .is_some_and(|mut archive| archive.by_name(MANIFEST_FILE).is_ok())
+}
+
+/// Remove the directories that removing files left empty, deepest first
+/// root: The export directory, removed too if nothing is left in it
+fn prune_dirs<'a>(root: &Path, url_paths: impl Iterator<Item = &'a String>) {
+    let mut dirs: Vec<PathBuf> = url_paths
+        .flat_map(|url_path| {
+            Manifest::local_path(root, url_path)
+                .ancestors()
+                .skip(1)
+                .map(Path::to_path_buf)
+                .collect::<Vec<_>>()
+        })
+        .filter(|dir| dir.starts_with(root))
+        .collect();
+    dirs.sort_by(|a, b| {
+        b.components()
+            .count()
+            .cmp(&a.components().count())
+            .then_with(|| a.cmp(b))
+    });
+    dirs.dedup();
+    for dir in dirs {
+        // fails for directories that still have something in them, which are kept
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+/// Remove a file or directory if it exists
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn site() -> SiteDetails {
+        SiteDetails {
+            name: Some("blog".to_string()),
+            id: Some("1".to_string()),
+            ssl: None,
+            url: None,
+            screenshot_url: None,
+        }
+    }
+
+    /// A built site with an index and one post
+    fn built_site(root: &Path) -> PathBuf {
+        let output = root.join("public");
+        fs::create_dir_all(output.join("posts")).unwrap();
+        fs::write(output.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(output.join("posts/hello.html"), "<p>Hello</p>").unwrap();
+        output
+    }
+
+    #[test]
+    fn exports_into_a_directory_and_deletes_only_what_it_wrote() {
+        let root = tempfile::tempdir().unwrap();
+        let output = built_site(root.path());
+        let destination = root.path().join("export");
+        let target = ExportTarget::new(&destination);
+
+        let manifest = target.export(&output).unwrap();
+        assert_eq!(
+            manifest.files.keys().collect::<Vec<_>>(),
+            ["/index.html", "/posts/hello.html"]
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("posts/hello.html")).unwrap(),
+            "<p>Hello</p>"
+        );
+        assert!(destination.join(MANIFEST_FILE).is_file());
+
+        // a file removed from the build is gone from the next export
+        fs::remove_file(output.join("posts/hello.html")).unwrap();
+        target.export(&output).unwrap();
+        assert!(!destination.join("posts/hello.html").exists());
+
+        fs::write(destination.join("notes.txt"), "mine").unwrap();
+        target.delete_site(&site()).unwrap();
+        assert!(!destination.join("index.html").exists());
+        assert!(!destination.join(MANIFEST_FILE).exists());
+        assert_eq!(
+            fs::read_to_string(destination.join("notes.txt")).unwrap(),
+            "mine"
+        );
+    }
+
+    #[test]
+    fn refuses_to_replace_a_directory_that_isnt_an_export() {
+        let root = tempfile::tempdir().unwrap();
+        let output = built_site(root.path());
+        let destination = root.path().join("project");
+        fs::create_dir_all(&destination).unwrap();
+        fs::write(destination.join("Cargo.toml"), "[package]").unwrap();
+        let target = ExportTarget::new(&destination);
+
+        assert!(target.export(&output).is_err());
+        assert!(target.delete_site(&site()).is_err());
+        assert!(destination.join("Cargo.toml").is_file());
+        assert!(!root.path().join(".project.tmp").exists());
+
+        // an earlier export with something else added to it is left alone too
+        let destination = root.path().join("export");
+        let target = ExportTarget::new(&destination);
+        target.export(&output).unwrap();
+        fs::write(destination.join("notes.txt"), "mine").unwrap();
+        assert!(target.export(&output).is_err());
+        assert!(destination.join("notes.txt").is_file());
+    }
+
+    #[test]
+    fn exports_into_a_zip_archive() {
+        let root = tempfile::tempdir().unwrap();
+        let output = built_site(root.path());
+        let destination = root.path().join("site.zip");
+        let target = ExportTarget::new(&destination);
+        assert_eq!(target.format(), ExportFormat::Zip);
+
+        target.export(&output).unwrap();
+        // exporting again replaces the earlier archive
+        target.export(&output).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&destination).unwrap()).unwrap();
+        let mut post = String::new();
+        archive
+            .by_name("posts/hello.html")
+            .unwrap()
+            .read_to_string(&mut post)
+            .unwrap();
+        assert_eq!(post, "<p>Hello</p>");
+        let mut manifest = Vec::new();
+        archive
+            .by_name(MANIFEST_FILE)
+            .unwrap()
+            .read_to_end(&mut manifest)
+            .unwrap();
+        assert!(Manifest::from_json(&manifest)
+            .unwrap()
+            .files
+            .contains_key("/index.html"));
+
+        target.delete_site(&site()).unwrap();
+        assert!(!destination.exists());
+    }
+
+    #[test]
+    fn refuses_to_replace_a_file_that_isnt_an_export() {
+        let root = tempfile::tempdir().unwrap();
+        let output = built_site(root.path());
+        let destination = root.path().join("backup.zip");
+        fs::write(&destination, "not a zip").unwrap();
+        let target = ExportTarget::new(&destination);
+
+        assert!(target.export(&output).is_err());
+        assert!(target.delete_site(&site()).is_err());
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "not a zip");
+    }
+}
@@ -0,0 +1,347 @@
+/// Feeds Module
+/// Writes the RSS 2.0 (`feed.xml`), Atom (`atom.xml`) and JSON Feed 1.1 (`feed.json`)
+/// files for a site, so readers can subscribe to it.
+use crate::build::SourcePost;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde_json::json;
+use std::{fs, path::Path};
+
+pub const RSS_FILE: &str = "feed.xml";
+pub const ATOM_FILE: &str = "atom.xml";
+pub const JSON_FEED_FILE: &str = "feed.json";
+
+/// FeedSite struct
+/// The site level details every feed needs
+/// title: The human readable name of the site
+/// base_url: The public URL of the site, without a trailing slash
+pub(crate) struct FeedSite<'a> {
+    pub title: &'a str,
+    pub base_url: &'a str,
+}
+
+impl FeedSite<'_> {
+    fn post_url(&self, post: &SourcePost) -> String {
+        format!("{}/posts/{}.html", self.base_url, post.slug)
+    }
+
+    fn description(&self) -> String {
+        format!("Latest posts from {}", self.title)
+    }
+}
+
+/// Write all three feeds into the output directory
+/// posts: The published posts, newest first
+/// limit: The maximum number of posts in each feed
+pub(crate) fn write_feeds(
+    site: &FeedSite,
+    posts: &[SourcePost],
+    limit: usize,
+    out_path: &Path,
+) -> Result<()> {
+    println!(">> Writing feeds");
+    let posts = &posts[..posts.len().min(limit)];
+
+    fs::write(out_path.join(RSS_FILE), rss(site, posts)).context("Failed to write the RSS feed")?;
+    fs::write(out_path.join(ATOM_FILE), atom(site, posts))
+        .context("Failed to write the Atom feed")?;
+    fs::write(out_path.join(JSON_FEED_FILE), json_feed(site, posts)?)
+        .context("Failed to write the JSON feed")?;
+
+    Ok(())
+}
+
+/// Build the RSS 2.0 feed
+fn rss(site: &FeedSite, posts: &[SourcePost]) -> String {
+    let mut items = String::new();
+    for post in posts {
+        let url = site.post_url(post);
+        items.push_str("    <item>\n");
+        items.push_str(&format!("      <title>{}</title>\n", escape_xml(&post.title)));
+        items.push_str(&format!("      <link>{}</link>\n", escape_xml(&url)));
+        items.push_str(&format!(
+            "      <guid isPermaLink=\"true\">{}</guid>\n",
+            escape_xml(&url)
+        ));
+        if let Some(date) = post.date.and_then(to_local) {
+            items.push_str(&format!("      <pubDate>{}</pubDate>\n", date.to_rfc2822()));
+        }
+        for tag in &post.front_matter.tags {
+            items.push_str(&format!("      <category>{}</category>\n", escape_xml(tag)));
+        }
+        items.push_str(&format!(
+            "      <description>{}</description>\n",
+            escape_xml(&summary(post))
+        ));
+        items.push_str("    </item>\n");
+    }
+
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n",
+            "  <channel>\n",
+            "    <title>{title}</title>\n",
+            "    <link>{link}/</link>\n",
+            "    <description>{description}</description>\n",
+            "    <atom:link href=\"{link}/{file}\" rel=\"self\" type=\"application/rss+xml\" />\n",
+            "    <lastBuildDate>{updated}</lastBuildDate>\n",
+            "{items}",
+            "  </channel>\n",
+            "</rss>\n"
+        ),
+        title = escape_xml(site.title),
+        link = escape_xml(site.base_url),
+        description = escape_xml(&site.description()),
+        file = RSS_FILE,
+        updated = feed_updated(posts).to_rfc2822(),
+        items = items,
+    )
+}
+
+/// Build the Atom feed
+fn atom(site: &FeedSite, posts: &[SourcePost]) -> String {
+    let mut entries = String::new();
+    for post in posts {
+        let url = site.post_url(post);
+        let updated = post
+            .lastmod()
+            .and_then(to_local)
+            .unwrap_or_else(|| feed_updated(posts));
+        entries.push_str("  <entry>\n");
+        entries.push_str(&format!("    <title>{}</title>\n", escape_xml(&post.title)));
+        entries.push_str(&format!("    <link href=\"{}\" />\n", escape_xml(&url)));
+        entries.push_str(&format!("    <id>{}</id>\n", escape_xml(&url)));
+        entries.push_str(&format!("    <updated>{}</updated>\n", updated.to_rfc3339()));
+        if let Some(date) = post.date.and_then(to_local) {
+            entries.push_str(&format!("    <published>{}</published>\n", date.to_rfc3339()));
+        }
+        for tag in &post.front_matter.tags {
+            entries.push_str(&format!("    <category term=\"{}\" />\n", escape_xml(tag)));
+        }
+        entries.push_str(&format!(
+            "    <summary>{}</summary>\n",
+            escape_xml(&summary(post))
+        ));
+        entries.push_str(&format!(
+            "    <content type=\"html\">{}</content>\n",
+            escape_xml(&post.content)
+        ));
+        entries.push_str("  </entry>\n");
+    }
+
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+            "  <title>{title}</title>\n",
+            "  <subtitle>{description}</subtitle>\n",
+            "  <link href=\"{link}/\" />\n",
+            "  <link href=\"{link}/{file}\" rel=\"self\" />\n",
+            "  <id>{link}/</id>\n",
+            "  <updated>{updated}</updated>\n",
+            "  <author><name>{title}</name></author>\n",
+            "{entries}",
+            "</feed>\n"
+        ),
+        title = escape_xml(site.title),
+        description = escape_xml(&site.description()),
+        link = escape_xml(site.base_url),
+        file = ATOM_FILE,
+        updated = feed_updated(posts).to_rfc3339(),
+        entries = entries,
+    )
+}
+
+/// Build the JSON Feed 1.1 feed
+fn json_feed(site: &FeedSite, posts: &[SourcePost]) -> Result<String> {
+    let items = posts
+        .iter()
+        .map(|post| {
+            let url = site.post_url(post);
+            let mut item = json!({
+                "id": url,
+                "url": url,
+                "title": post.title,
+                "summary": summary(post),
+                "content_html": post.content,
+                "tags": post.front_matter.tags,
+            });
+            if let Some(date) = post.date.and_then(to_local) {
+                item["date_published"] = json!(date.to_rfc3339());
+            }
+            if let Some(updated) = post.front_matter.parsed_updated().and_then(to_local) {
+                item["date_modified"] = json!(updated.to_rfc3339());
+            }
+            if let Some(image) = &post.front_matter.image {
+                item["image"] = json!(image);
+            }
+            item
+        })
+        .collect::<Vec<_>>();
+
+    let feed = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": site.title,
+        "description": site.description(),
+        "home_page_url": format!("{}/", site.base_url),
+        "feed_url": format!("{}/{}", site.base_url, JSON_FEED_FILE),
+        "items": items,
+    });
+
+    serde_json::to_string_pretty(&feed).context("Failed to serialize the JSON feed")
+}
+
+/// The excerpt of a post, or its title if it has none
+fn summary(post: &SourcePost) -> String {
+    post.front_matter
+        .excerpt
+        .clone()
+        .unwrap_or_else(|| post.title.clone())
+}
+
+/// The time the feed was last updated, the date of the newest post
+fn feed_updated(posts: &[SourcePost]) -> DateTime<Local> {
+    posts
+        .iter()
+        .filter_map(|post| post.lastmod())
+        .max()
+        .and_then(to_local)
+        .unwrap_or_else(Local::now)
+}
+
+/// Attach the local time zone to a front matter date
+pub(crate) fn to_local(date: NaiveDateTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&date).earliest()
+}
+
+/// Escape text for use in XML content and attributes
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::front_matter::{FrontMatter, PostState};
+    use quick_xml::{events::Event, Reader};
+
+    const SITE: FeedSite = FeedSite {
+        title: "Tom & Jerry's <Blog>",
+        base_url: "https://blog.example.com",
+    };
+
+    /// A published post written on the given day of May 2024
+    fn post(slug: &str, day: u32, excerpt: Option<&str>) -> SourcePost {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(9, 0, 0);
+        SourcePost {
+            slug: slug.to_string(),
+            title: format!("Post {} <{}> & more", day, slug),
+            date,
+            state: PostState::Published,
+            front_matter: FrontMatter {
+                excerpt: excerpt.map(String::from),
+                tags: vec!["rust".to_string()],
+                ..FrontMatter::default()
+            },
+            content: "<p>Hi</p>".to_string(),
+        }
+    }
+
+    /// Check that an XML document is well formed and count its elements with the given name
+    fn count_elements(xml: &str, name: &[u8]) -> usize {
+        let mut reader = Reader::from_str(xml);
+        let mut count = 0;
+        loop {
+            match reader.read_event().expect("the feed isn't well formed XML") {
+                Event::Start(element) if element.name().as_ref() == name => count += 1,
+                Event::Eof => return count,
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn every_feed_is_capped_at_the_limit() {
+        let out = tempfile::tempdir().unwrap();
+        let posts = (1..=5)
+            .rev()
+            .map(|day| post(&format!("post-{}", day), day, None))
+            .collect::<Vec<_>>();
+        write_feeds(&SITE, &posts, 3, out.path()).unwrap();
+
+        let rss = fs::read_to_string(out.path().join(RSS_FILE)).unwrap();
+        assert_eq!(count_elements(&rss, b"item"), 3);
+        let atom = fs::read_to_string(out.path().join(ATOM_FILE)).unwrap();
+        assert_eq!(count_elements(&atom, b"entry"), 3);
+        let json: serde_json::Value =
+            serde_json::from_slice(&fs::read(out.path().join(JSON_FEED_FILE)).unwrap()).unwrap();
+        assert_eq!(json["items"].as_array().unwrap().len(), 3);
+        // the newest posts are the ones kept
+        assert_eq!(
+            json["items"][0]["url"],
+            "https://blog.example.com/posts/post-5.html"
+        );
+        assert_eq!(
+            json["items"][2]["url"],
+            "https://blog.example.com/posts/post-3.html"
+        );
+    }
+
+    #[test]
+    fn rss_links_are_absolute_and_text_is_escaped() {
+        let rss = rss(&SITE, &[post("hello", 1, Some("Fish & \"chips\" <3"))]);
+
+        assert_eq!(count_elements(&rss, b"item"), 1);
+        assert!(rss.contains("<title>Tom &amp; Jerry&apos;s &lt;Blog&gt;</title>"));
+        assert!(rss.contains("<link>https://blog.example.com/</link>"));
+        assert!(rss.contains("<link>https://blog.example.com/posts/hello.html</link>"));
+        assert!(rss.contains("href=\"https://blog.example.com/feed.xml\""));
+        assert!(rss.contains("<title>Post 1 &lt;hello&gt; &amp; more</title>"));
+        assert!(rss.contains("<description>Fish &amp; &quot;chips&quot; &lt;3</description>"));
+    }
+
+    #[test]
+    fn atom_links_are_absolute_and_text_is_escaped() {
+        let atom = atom(&SITE, &[post("hello", 1, None)]);
+
+        assert_eq!(count_elements(&atom, b"entry"), 1);
+        assert!(atom.contains("<link href=\"https://blog.example.com/posts/hello.html\" />"));
+        assert!(atom.contains("<id>https://blog.example.com/posts/hello.html</id>"));
+        assert!(atom.contains("<link href=\"https://blog.example.com/atom.xml\" rel=\"self\" />"));
+        // a post without an excerpt is summarised by its title
+        assert!(atom.contains("<summary>Post 1 &lt;hello&gt; &amp; more</summary>"));
+        assert!(atom.contains("<content type=\"html\">&lt;p&gt;Hi&lt;/p&gt;</content>"));
+    }
+
+    #[test]
+    fn json_feed_follows_version_1_1() {
+        let feed: serde_json::Value = serde_json::from_str(
+            &json_feed(&SITE, &[post("hello", 1, Some("Hi \"there\""))]).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(feed["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(feed["title"], "Tom & Jerry's <Blog>");
+        assert_eq!(feed["home_page_url"], "https://blog.example.com/");
+        assert_eq!(feed["feed_url"], "https://blog.example.com/feed.json");
+
+        let item = &feed["items"][0];
+        assert_eq!(item["id"], "https://blog.example.com/posts/hello.html");
+        assert_eq!(item["url"], item["id"]);
+        assert_eq!(item["title"], "Post 1 <hello> & more");
+        assert_eq!(item["summary"], "Hi \"there\"");
+        assert_eq!(item["content_html"], "<p>Hi</p>");
+        assert_eq!(item["tags"], serde_json::json!(["rust"]));
+        assert!(item["date_published"]
+            .as_str()
+            .unwrap()
+            .starts_with("2024-05-01T09:00:00"));
+    }
+}
@@ -0,0 +1,300 @@
+/// Front Matter Module
+/// Parses the metadata block at the top of a markdown post.
+/// YAML blocks are delimited by `---`, TOML blocks by `+++`.
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+const YAML_DELIMITER: &str = "---";
+const TOML_DELIMITER: &str = "+++";
+
+/// Date formats accepted in front matter, tried in order
+/// The last one is the format older versions of Driftwood wrote
+const DATE_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y/%m/%d %I:%M %p",
+];
+
+/// PostState enum
+/// Whether a post is part of a production build
+/// Published: The post is live
+/// Draft: The post is marked as a draft
+/// Scheduled: The post goes live once the given time has passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostState {
+    Published,
+    Draft,
+    Scheduled(NaiveDateTime),
+}
+
+/// FrontMatter struct
+/// The metadata of a post
+/// title: The title of the post, defaults to one built from the file name
+/// date: The date the post was written
+/// updated: The date the post was last updated
+/// excerpt: A short summary shown on the blog card
+/// image: The image shown on the blog card
+/// tags: The tags of the post
+/// draft: Whether the post is still a draft, drafts are left out of production builds
+/// publish_at: The post stays hidden until this time has passed
+/// slug: The output file name of the post, defaults to the markdown file name
+/// extra: Any other fields, passed through to the templates as-is
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FrontMatter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excerpt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publish_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl FrontMatter {
+    /// Split a markdown source into its front matter and body
+    /// Sources without a front matter block fall back to the trailing
+    /// `date:`/`excerpt:`/`image:`/`tags:` lines older posts were written with
+    pub fn parse(source: &str) -> Result<(FrontMatter, String)> {
+        let source = source.trim_start_matches('\u{feff}');
+        let first_line = source.lines().next().unwrap_or_default().trim_end();
+
+        if first_line == YAML_DELIMITER {
+            let (block, body) = split_block(source, YAML_DELIMITER)?;
+            let front_matter = if block.trim().is_empty() {
+                FrontMatter::default()
+            } else {
+                serde_yaml::from_str(block).context("Failed to parse YAML front matter")?
+            };
+            Ok((front_matter, body.to_string()))
+        } else if first_line == TOML_DELIMITER {
+            let (block, body) = split_block(source, TOML_DELIMITER)?;
+            let table: toml::Table =
+                toml::from_str(block).context("Failed to parse TOML front matter")?;
+            // TOML has a native datetime type, turn those into plain strings first
+            let json = toml_to_json(toml::Value::Table(table));
+            let front_matter =
+                serde_json::from_value(json).context("Failed to read TOML front matter")?;
+            Ok((front_matter, body.to_string()))
+        } else {
+            Ok(FrontMatter::parse_legacy(source))
+        }
+    }
+
+    /// Render the front matter as a YAML block, delimiters included
+    pub fn to_yaml_block(&self) -> Result<String> {
+        let yaml = serde_yaml::to_string(self).context("Failed to serialize front matter")?;
+        Ok(format!("{}\n{}{}\n", YAML_DELIMITER, yaml, YAML_DELIMITER))
+    }
+
+    /// Parse the post date, if there is one in a known format
+    pub fn parsed_date(&self) -> Option<NaiveDateTime> {
+        self.date.as_deref().and_then(parse_date)
+    }
+
+    /// Parse the updated date, if there is one in a known format
+    pub fn parsed_updated(&self) -> Option<NaiveDateTime> {
+        self.updated.as_deref().and_then(parse_date)
+    }
+
+    /// Parse the publish time, if there is one in a known format
+    pub fn parsed_publish_at(&self) -> Option<NaiveDateTime> {
+        self.publish_at.as_deref().and_then(parse_date)
+    }
+
+    /// Work out whether the post is published at the given (local) time
+    /// A draft stays a draft even after its publish time has passed
+    pub fn state(&self, now: NaiveDateTime) -> PostState {
+        if self.draft {
+            return PostState::Draft;
+        }
+        match self.parsed_publish_at() {
+            Some(publish_at) if publish_at > now => PostState::Scheduled(publish_at),
+            _ => PostState::Published,
+        }
+    }
+
+    /// Read the metadata lines older versions of Driftwood appended to a post
+    fn parse_legacy(source: &str) -> (FrontMatter, String) {
+        let mut front_matter = FrontMatter::default();
+        let mut body = String::new();
+
+        for line in source.lines() {
+            let line_trimmed = line.trim();
+            if let Some(value) = line_trimmed.strip_prefix("date:") {
+                front_matter.date = Some(value.trim().to_string());
+            } else if let Some(value) = line_trimmed.strip_prefix("excerpt:") {
+                front_matter.excerpt = Some(value.trim().to_string());
+            } else if let Some(value) = line_trimmed.strip_prefix("image:") {
+                front_matter.image = Some(value.trim().to_string());
+            } else if let Some(value) = line_trimmed.strip_prefix("tags:") {
+                front_matter.tags = value
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+            } else {
+                body.push_str(line);
+                body.push('\n');
+            }
+        }
+
+        (front_matter, body)
+    }
+}
+
+impl fmt::Display for PostState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostState::Published => write!(f, "published"),
+            PostState::Draft => write!(f, "draft"),
+            PostState::Scheduled(publish_at) => {
+                write!(f, "scheduled for {}", publish_at.format("%Y-%m-%d %H:%M"))
+            }
+        }
+    }
+}
+
+/// Parse a date in any of the formats front matter accepts
+/// Dates are local time, RFC 3339 dates are converted from their offset
+pub fn parse_date(date: &str) -> Option<NaiveDateTime> {
+    let date = date.trim();
+    if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(date) {
+        return Some(date_time.with_timezone(&Local).naive_local());
+    }
+    for format in DATE_FORMATS {
+        if let Ok(date_time) = NaiveDateTime::parse_from_str(date, format) {
+            return Some(date_time);
+        }
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+}
+
+/// Split `<delimiter>\n<block>\n<delimiter>\n<body>` into block and body
+fn split_block<'a>(source: &'a str, delimiter: &str) -> Result<(&'a str, &'a str)> {
+    let after_open = source
+        .split_once('\n')
+        .map(|(_, rest)| rest)
+        .unwrap_or_default();
+
+    let mut offset = 0;
+    for line in after_open.split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            let block = &after_open[..offset];
+            let body = &after_open[offset + line.len()..];
+            return Ok((block, body));
+        }
+        offset += line.len();
+    }
+
+    Err(anyhow::anyhow!(
+        "Front matter is missing its closing '{}'",
+        delimiter
+    ))
+}
+
+/// Convert a TOML value into JSON, turning datetimes into strings
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(s) => serde_json::Value::String(s),
+        toml::Value::Integer(i) => serde_json::Value::from(i),
+        toml::Value::Float(f) => serde_json::Value::from(f),
+        toml::Value::Boolean(b) => serde_json::Value::Bool(b),
+        toml::Value::Datetime(d) => serde_json::Value::String(d.to_string()),
+        toml::Value::Array(a) => {
+            serde_json::Value::Array(a.into_iter().map(toml_to_json).collect())
+        }
+        toml::Value::Table(t) => serde_json::Value::Object(
+            t.into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn parses_yaml_front_matter() {
+        let source = "---\ntitle: Hello\ndate: 2024-05-01 09:30:00\ntags: [rust, web]\nslug: hi\nmood: happy\n---\n# Body\n";
+        let (front_matter, body) = FrontMatter::parse(source).unwrap();
+
+        assert_eq!(front_matter.title.as_deref(), Some("Hello"));
+        assert_eq!(front_matter.parsed_date(), Some(at("2024-05-01 09:30:00")));
+        assert_eq!(front_matter.tags, ["rust", "web"]);
+        assert_eq!(front_matter.slug.as_deref(), Some("hi"));
+        assert_eq!(front_matter.extra["mood"], "happy");
+        assert!(!front_matter.draft);
+        assert_eq!(body, "# Body\n");
+    }
+
+    #[test]
+    fn parses_toml_front_matter_with_native_dates() {
+        let source = "+++\ntitle = \"Hello\"\ndate = 2024-05-01T09:30:00\ndraft = true\ntags = [\"rust\"]\n+++\nBody\n";
+        let (front_matter, body) = FrontMatter::parse(source).unwrap();
+
+        assert_eq!(front_matter.title.as_deref(), Some("Hello"));
+        assert_eq!(front_matter.parsed_date(), Some(at("2024-05-01 09:30:00")));
+        assert_eq!(front_matter.tags, ["rust"]);
+        assert_eq!(
+            front_matter.state(at("2024-06-01 00:00:00")),
+            PostState::Draft
+        );
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn rejects_broken_front_matter() {
+        assert!(FrontMatter::parse("---\ntitle: Hello\nno closing delimiter\n").is_err());
+        assert!(FrontMatter::parse("+++\ntitle = \n+++\nBody\n").is_err());
+    }
+
+    #[test]
+    fn reads_the_metadata_lines_of_older_posts() {
+        let source = "Body line\ndate: 2024/05/01 09:30 AM\ntags: rust, web\n";
+        let (front_matter, body) = FrontMatter::parse(source).unwrap();
+
+        assert_eq!(front_matter.parsed_date(), Some(at("2024-05-01 09:30:00")));
+        assert_eq!(front_matter.tags, ["rust", "web"]);
+        assert_eq!(body, "Body line\n");
+    }
+
+    #[test]
+    fn scheduled_posts_go_live_at_their_publish_time() {
+        let front_matter = FrontMatter {
+            publish_at: Some("2024-05-01 09:00:00".to_string()),
+            ..FrontMatter::default()
+        };
+        assert_eq!(
+            front_matter.state(at("2024-04-30 12:00:00")),
+            PostState::Scheduled(at("2024-05-01 09:00:00"))
+        );
+        assert_eq!(
+            front_matter.state(at("2024-05-01 09:00:01")),
+            PostState::Published
+        );
+    }
+}
@@ -0,0 +1,292 @@
+/// Git Pages Module
+/// A hosting provider that commits the built site to a `gh-pages` style branch of the
+/// site's repository and pushes it, so any host that serves a git branch can publish it.
+use crate::config::{GitPagesConfig, SiteConfig};
+use crate::hosting::{
+    list_local_sites, new_local_site, rename_local_site, Deployment, HostingProvider,
+};
+use crate::manifest::Manifest;
+use crate::SiteDetails;
+use anyhow::{anyhow, Context, Result};
+use git2::{
+    Cred, CredentialType, FileMode, Oid, PushOptions, Remote, RemoteCallbacks, Repository,
+    Signature,
+};
+use std::{fs, path::Path};
+
+/// Tells GitHub Pages to serve the files as they are, without running Jekyll
+const NOJEKYLL_FILE: &str = ".nojekyll";
+/// Holds the custom domain of the site
+const CNAME_FILE: &str = "CNAME";
+
+/// GitPagesTarget struct
+/// config: Overrides the `[git_pages]` section of the site's config file
+#[derive(Default)]
+pub struct GitPagesTarget {
+    config: Option<GitPagesConfig>,
+}
+
+impl GitPagesTarget {
+    /// Create a target that reads its settings from each site's config file
+    pub fn new() -> GitPagesTarget {
+        GitPagesTarget { config: None }
+    }
+
+    /// Create a target with the given settings, ignoring the site's config file
+    pub fn with_config(config: GitPagesConfig) -> GitPagesTarget {
+        GitPagesTarget {
+            config: Some(config),
+        }
+    }
+
+    /// Commit a built site to the branch and push it
+    /// repo_path: The repository to commit to, created if it doesn't exist
+    /// output_path: The directory the site was built into
+    /// Returns the ID of the commit that was pushed
+    pub fn publish(
+        &self,
+        repo_path: &Path,
+        output_path: &Path,
+        config: &GitPagesConfig,
+    ) -> Result<Oid> {
+        println!(
+            ">> Publishing {} to the '{}' branch of {}",
+            output_path.display(),
+            config.branch,
+            config.remote
+        );
+
+        if !output_path.join("index.html").exists() {
+            return Err(anyhow!("index.html not found in {}", output_path.display()));
+        }
+
+        let repo = match Repository::open(repo_path) {
+            Ok(repo) => repo,
+            Err(_) => Repository::init(repo_path).with_context(|| {
+                format!("Failed to create a repository in {}", repo_path.display())
+            })?,
+        };
+
+        let commit_id = commit_output(&repo, output_path, config)?;
+        let refspec = branch_ref(config);
+        // the branch only ever holds builds, so the remote copy is always replaced
+        push(&repo, &config.remote, &format!("+{0}:{0}", refspec))?;
+
+        println!(">> Pushed {} to {}", commit_id, config.remote);
+        Ok(commit_id)
+    }
+
+    /// The settings for a site, from the override or the site's config file
+    fn config_for(&self, site: &SiteDetails) -> Result<GitPagesConfig> {
+        if let Some(config) = &self.config {
+            return Ok(config.clone());
+        }
+        SiteConfig::load(site)?.git_pages.ok_or_else(|| {
+            anyhow!("Add a [git_pages] section with a remote to the site's driftwood.toml")
+        })
+    }
+}
+
+impl HostingProvider for GitPagesTarget {
+    fn name(&self) -> &str {
+        "Git pages"
+    }
+
+    fn create_site(&self, name: &str) -> Result<SiteDetails> {
+        Ok(new_local_site(name))
+    }
+
+    fn list_sites(&self) -> Result<Vec<SiteDetails>> {
+        list_local_sites()
+    }
+
+    fn update_site(&self, site: &SiteDetails, new_details: SiteDetails) -> Result<SiteDetails> {
+        rename_local_site(site, new_details)
+    }
+
+    /// Deletes the branch from the remote, the site's sources and local history are kept
+    fn delete_site(&self, site: &SiteDetails) -> Result<()> {
+        let config = self.config_for(site)?;
+        let repo = Repository::open(site.build_site_path()?)
+            .context("The site directory is not a git repository")?;
+        push(&repo, &config.remote, &format!(":{}", branch_ref(&config)))
+    }
+
+    fn deploy(&self, site: &SiteDetails, output_path: &Path) -> Result<Deployment> {
+        let config = self.config_for(site)?;
+        let commit_id = self.publish(&site.build_site_path()?, output_path, &config)?;
+        Ok(Deployment {
+            id: Some(commit_id.to_string()),
+            url: config.cname.map(|cname| format!("https://{}", cname)),
+        })
+    }
+}
+
+/// The full name of the branch, e.g. `refs/heads/gh-pages`
+fn branch_ref(config: &GitPagesConfig) -> String {
+    format!("refs/heads/{}", config.branch)
+}
+
+/// Commit the built site to the branch without touching the working directory
+/// The first commit starts the branch as an orphan, later ones build on it
+/// Returns the ID of the branch's newest commit
+fn commit_output(repo: &Repository, output_path: &Path, config: &GitPagesConfig) -> Result<Oid> {
+    let mut root = repo.treebuilder(None)?;
+    insert_dir(repo, &mut root, output_path)?;
+
+    let nojekyll = repo.blob(b"")?;
+    root.insert(NOJEKYLL_FILE, nojekyll, FileMode::Blob.into())?;
+    if let Some(cname) = &config.cname {
+        let cname = repo.blob(format!("{}\n", cname.trim()).as_bytes())?;
+        root.insert(CNAME_FILE, cname, FileMode::Blob.into())?;
+    }
+    let tree = repo.find_tree(root.write()?)?;
+
+    let branch = branch_ref(config);
+    let parent = repo
+        .find_reference(&branch)
+        .ok()
+        .and_then(|reference| reference.peel_to_commit().ok());
+
+    // nothing changed since the last deploy
+    if let Some(parent) = &parent {
+        if parent.tree_id() == tree.id() {
+            println!(">> No changes since the last deploy");
+            return Ok(parent.id());
+        }
+    }
+
+    let signature = Signature::now("Driftwood", "driftwood@example.com")?;
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    let message = format!(
+        "Deploy site: {}",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    let commit_id = repo
+        .commit(
+            Some(&branch),
+            &signature,
+            &signature,
+            &message,
+            &tree,
+            &parents,
+        )
+        .context("Failed to commit the built site")?;
+    Ok(commit_id)
+}
+
+/// Add every file below a directory to a tree
+/// Empty directories and the files the manifest ignores are left out
+fn insert_dir(repo: &Repository, builder: &mut git2::TreeBuilder, dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if Manifest::is_ignored(&path) {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default();
+        if path.is_dir() {
+            let mut subtree = repo.treebuilder(None)?;
+            insert_dir(repo, &mut subtree, &path)?;
+            if !subtree.is_empty() {
+                builder.insert(name, subtree.write()?, FileMode::Tree.into())?;
+            }
+        } else if path.is_file() {
+            let blob = repo
+                .blob_path(&path)
+                .with_context(|| format!("Failed to add {}", path.display()))?;
+            builder.insert(name, blob, FileMode::Blob.into())?;
+        }
+    }
+    Ok(())
+}
+
+/// Push a refspec to a named remote of the repository, or to a URL or path
+fn push(repo: &Repository, remote: &str, refspec: &str) -> Result<()> {
+    let mut remote: Remote = match repo.find_remote(remote) {
+        Ok(named) => named,
+        Err(_) => repo.remote_anonymous(remote)?,
+    };
+    let git_config = repo.config()?;
+
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|url, username, allowed| {
+        if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            Cred::credential_helper(&git_config, url, username)
+        } else {
+            Cred::default()
+        }
+    });
+    // a rejected ref doesn't fail the push by itself
+    callbacks.push_update_reference(|refname, status| match status {
+        Some(message) => Err(git2::Error::from_str(&format!(
+            "{} was rejected: {}",
+            refname, message
+        ))),
+        None => Ok(()),
+    });
+
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+    remote
+        .push(&[refspec], Some(&mut options))
+        .with_context(|| format!("Failed to push to {}", remote.url().unwrap_or("the remote")))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publishes_the_build_to_a_bare_remote() {
+        let root = tempfile::tempdir().unwrap();
+        let remote_path = root.path().join("remote.git");
+        let remote = Repository::init_bare(&remote_path).unwrap();
+        let output = root.path().join("public");
+        fs::create_dir_all(output.join("posts")).unwrap();
+        fs::write(output.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(output.join("posts/hello.html"), "<p>Hello</p>").unwrap();
+
+        let config = GitPagesConfig {
+            remote: remote_path.to_str().unwrap().to_string(),
+            branch: "gh-pages".to_string(),
+            cname: Some("blog.example.com".to_string()),
+        };
+        let target = GitPagesTarget::with_config(config.clone());
+        let repo_path = root.path().join("site");
+        let first = target.publish(&repo_path, &output, &config).unwrap();
+
+        let pushed = remote
+            .find_reference("refs/heads/gh-pages")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        assert_eq!(pushed.id(), first);
+        let tree = pushed.tree().unwrap();
+        let post = tree.get_path(Path::new("posts/hello.html")).unwrap();
+        assert_eq!(
+            remote.find_blob(post.id()).unwrap().content(),
+            b"<p>Hello</p>"
+        );
+        assert!(tree.get_name(NOJEKYLL_FILE).is_some());
+        let cname = tree.get_name(CNAME_FILE).unwrap();
+        assert_eq!(
+            remote.find_blob(cname.id()).unwrap().content(),
+            b"blog.example.com\n"
+        );
+
+        // an unchanged build isn't committed again, a changed one builds on the last deploy
+        assert_eq!(target.publish(&repo_path, &output, &config).unwrap(), first);
+        fs::write(output.join("index.html"), "<h1>New home</h1>").unwrap();
+        let second = target.publish(&repo_path, &output, &config).unwrap();
+        let pushed = remote
+            .find_reference("refs/heads/gh-pages")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        assert_eq!(pushed.id(), second);
+        assert_eq!(pushed.parent_id(0).unwrap(), first);
+    }
+}
@@ -0,0 +1,278 @@
+/// History Module
+/// Keeps a local log of every deploy of a site, whichever provider it went to,
+/// so the outcome of past deploys can be looked up without asking the host.
+/// Each deploy is linked to the commit of the site's repository it was built from.
+use crate::SiteDetails;
+use anyhow::{Context, Result};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Name of the history file, kept in the site's directory next to its sources
+pub const HISTORY_FILE: &str = ".driftwood-deploys.json";
+
+/// DeployRecord struct
+/// The outcome of a single deploy
+/// deployed_at: When the deploy finished, in RFC 3339 form
+/// provider: The name of the hosting provider the site was deployed to
+/// id: The provider's ID for the deploy, if it has one
+/// url: Where the deployed site can be seen, if known
+/// state: `ready` if the deploy went live, `draft` for a preview, `error` if it failed
+/// error: What went wrong, for failed deploys
+/// commit: The ID of the site repository's commit the deploy was built from
+/// commit_message: The message of that commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployRecord {
+    pub deployed_at: String,
+    pub provider: String,
+    pub id: Option<String>,
+    pub url: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub commit: Option<String>,
+    #[serde(default)]
+    pub commit_message: Option<String>,
+}
+
+/// SiteCommit struct
+/// A commit of a site's repository
+/// id: The full ID of the commit
+/// message: The first line of the commit message
+#[derive(Debug, Clone)]
+pub struct SiteCommit {
+    pub id: String,
+    pub message: String,
+}
+
+impl DeployRecord {
+    /// A deploy that went live
+    pub fn ready(provider: &str, id: Option<String>, url: Option<String>) -> DeployRecord {
+        DeployRecord {
+            deployed_at: chrono::Local::now().to_rfc3339(),
+            provider: provider.to_string(),
+            id,
+            url,
+            state: "ready".to_string(),
+            error: None,
+            commit: None,
+            commit_message: None,
+        }
+    }
+
+    /// A preview deploy, which isn't live until it's promoted
+    pub fn draft(provider: &str, id: Option<String>, url: Option<String>) -> DeployRecord {
+        DeployRecord {
+            state: "draft".to_string(),
+            ..DeployRecord::ready(provider, id, url)
+        }
+    }
+
+    /// A previous deploy that was put live again
+    /// It keeps the commit it was built from, if the history has the deploy
+    /// history: The site's history, to look the deploy up in
+    pub fn restored(
+        provider: &str,
+        id: &str,
+        url: Option<String>,
+        history: &[DeployRecord],
+    ) -> DeployRecord {
+        let built = find_deploy(history, id);
+        DeployRecord {
+            commit: built.as_ref().and_then(|record| record.commit.clone()),
+            commit_message: built.and_then(|record| record.commit_message),
+            ..DeployRecord::ready(provider, Some(id.to_string()), url)
+        }
+    }
+
+    /// A deploy that failed
+    pub fn failed(provider: &str, error: String) -> DeployRecord {
+        DeployRecord {
+            deployed_at: chrono::Local::now().to_rfc3339(),
+            provider: provider.to_string(),
+            id: None,
+            url: None,
+            state: "error".to_string(),
+            error: Some(error),
+            commit: None,
+            commit_message: None,
+        }
+    }
+
+    /// Link the deploy to the commit it was built from
+    pub fn with_commit(mut self, commit: Option<SiteCommit>) -> DeployRecord {
+        if let Some(commit) = commit {
+            self.commit = Some(commit.id);
+            self.commit_message = Some(commit.message);
+        }
+        self
+    }
+}
+
+/// The path of a site's history file
+pub fn history_path(site: &SiteDetails) -> Result<PathBuf> {
+    Ok(site.build_site_path()?.join(HISTORY_FILE))
+}
+
+/// Every recorded deploy of a site, oldest first
+/// A site that has never been deployed has an empty history
+pub fn load_history(site: &SiteDetails) -> Result<Vec<DeployRecord>> {
+    read_history(&history_path(site)?)
+}
+
+/// Add a deploy to the end of a site's history
+pub fn record_deploy(site: &SiteDetails, record: DeployRecord) -> Result<()> {
+    append_history(&history_path(site)?, record)
+}
+
+/// Every deploy in a history file, an empty history if there's no file
+pub fn read_history(path: &Path) -> Result<Vec<DeployRecord>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Add a deploy to the end of a history file, creating it if needed
+pub fn append_history(path: &Path, record: DeployRecord) -> Result<()> {
+    let mut history = read_history(path)?;
+    history.push(record);
+    fs::write(path, serde_json::to_string_pretty(&history)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The commit a site's repository is on, which is what gets built and deployed
+/// None if the site has no repository or nothing has been committed yet
+pub fn site_commit(site: &SiteDetails) -> Option<SiteCommit> {
+    let repo = Repository::open(site.build_site_path().ok()?).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(SiteCommit {
+        id: commit.id().to_string(),
+        message: commit.summary().unwrap_or_default().to_string(),
+    })
+}
+
+/// The recorded deploy with the given provider ID, the newest if it was recorded more than once
+pub fn find_deploy(history: &[DeployRecord], deploy_id: &str) -> Option<DeployRecord> {
+    history
+        .iter()
+        .rev()
+        .find(|record| record.id.as_deref() == Some(deploy_id))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_restored_deploy_keeps_the_commit_it_was_built_from() {
+        let built = DeployRecord::ready("Netlify", Some("abc".to_string()), None).with_commit(
+            Some(SiteCommit {
+                id: "1234567".to_string(),
+                message: "Add a post".to_string(),
+            }),
+        );
+        let history = [
+            built,
+            DeployRecord::ready("Netlify", Some("def".to_string()), None),
+        ];
+
+        let restored = DeployRecord::restored("Netlify", "abc", None, &history);
+        assert_eq!(restored.id.as_deref(), Some("abc"));
+        assert_eq!(restored.state, "ready");
+        assert_eq!(restored.commit.as_deref(), Some("1234567"));
+        assert_eq!(restored.commit_message.as_deref(), Some("Add a post"));
+
+        let unknown = DeployRecord::restored("Netlify", "ghi", None, &history);
+        assert_eq!(unknown.commit, None);
+    }
+
+    #[test]
+    fn deploys_are_appended_and_read_back_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(HISTORY_FILE);
+        assert!(read_history(&path).unwrap().is_empty());
+
+        let commit = SiteCommit {
+            id: "1234567".to_string(),
+            message: "Add a post".to_string(),
+        };
+        append_history(
+            &path,
+            DeployRecord::ready("Netlify", Some("abc".to_string()), None).with_commit(Some(commit)),
+        )
+        .unwrap();
+        append_history(
+            &path,
+            DeployRecord::failed("Netlify", "Upload failed".to_string()),
+        )
+        .unwrap();
+        append_history(
+            &path,
+            DeployRecord::draft(
+                "Netlify",
+                Some("def".to_string()),
+                Some("https://def--blog.netlify.app".to_string()),
+            ),
+        )
+        .unwrap();
+
+        let history = read_history(&path).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|record| record.state.as_str())
+                .collect::<Vec<_>>(),
+            ["ready", "error", "draft"]
+        );
+        assert_eq!(history[1].error.as_deref(), Some("Upload failed"));
+
+        let found = find_deploy(&history, "abc").unwrap();
+        assert_eq!(found.commit.as_deref(), Some("1234567"));
+        assert_eq!(found.commit_message.as_deref(), Some("Add a post"));
+        assert_eq!(
+            find_deploy(&history, "def").unwrap().url.as_deref(),
+            Some("https://def--blog.netlify.app")
+        );
+        assert!(find_deploy(&history, "ghi").is_none());
+    }
+
+    #[test]
+    fn the_newest_record_of_a_deploy_is_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(HISTORY_FILE);
+        append_history(
+            &path,
+            DeployRecord::draft("Netlify", Some("abc".to_string()), None),
+        )
+        .unwrap();
+        append_history(
+            &path,
+            DeployRecord::ready("Netlify", Some("abc".to_string()), None),
+        )
+        .unwrap();
+
+        let history = read_history(&path).unwrap();
+        assert_eq!(find_deploy(&history, "abc").unwrap().state, "ready");
+    }
+
+    #[test]
+    fn records_from_before_commits_were_kept_still_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(HISTORY_FILE);
+        fs::write(
+            &path,
+            r#"[{"deployed_at":"2024-05-01T09:30:00+00:00","provider":"S3","id":null,"url":null,"state":"ready","error":null}]"#,
+        )
+        .unwrap();
+
+        let history = read_history(&path).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].commit, None);
+    }
+}
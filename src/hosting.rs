@@ -0,0 +1,163 @@
+/// Hosting Module
+/// The interface every hosting provider implements.
+/// The CLI only talks to this trait, so new backends plug in without touching the menus.
+use crate::SiteDetails;
+use anyhow::{anyhow, Context, Result};
+use std::{fs, path::Path};
+
+/// Directory every local site lives in
+const SITES_DIR: &str = "sites";
+
+/// SslCert struct
+/// Contains the details of an SSL certificate
+/// Fields match Netlify's API for provisioning an SSL certificate
+/// cert: The SSL certificate
+/// key: The SSL certificate key
+/// ca_cert: The SSL certificate CA
+pub struct SslCert {
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub ca_cert: Option<String>,
+}
+
+/// Deployment struct
+/// The result of deploying a site
+/// id: The provider's ID for the deploy, if it has one
+/// url: Where the deployed site can be seen, if known
+#[derive(Debug, Clone, Default)]
+pub struct Deployment {
+    pub id: Option<String>,
+    pub url: Option<String>,
+}
+
+/// DeployInfo struct
+/// A previous deploy of a site
+/// id: The provider's ID for the deploy
+/// created_at: When the deploy was created
+/// state: Where the deploy is up to, e.g. `ready` or `error`
+/// draft: Whether the deploy is a preview that was never published
+/// live: Whether this is the deploy the site is serving
+/// message: The message of the site repository's commit the deploy was built from
+/// commit: The ID of that commit, if the deploy is in the site's local history
+#[derive(Debug, Clone, Default)]
+pub struct DeployInfo {
+    pub id: String,
+    pub created_at: Option<String>,
+    pub state: Option<String>,
+    pub draft: bool,
+    pub live: bool,
+    pub message: Option<String>,
+    pub commit: Option<String>,
+}
+
+/// HostingProvider trait
+/// Creates, lists, updates and deletes sites, and deploys built sites
+pub trait HostingProvider {
+    /// A human readable name for the provider
+    fn name(&self) -> &str;
+
+    /// Create a new site with the given name
+    fn create_site(&self, name: &str) -> Result<SiteDetails>;
+
+    /// List all the sites the user has with this provider
+    fn list_sites(&self) -> Result<Vec<SiteDetails>>;
+
+    /// Update a site's details
+    /// Returns the site's details as the provider now has them
+    fn update_site(&self, site: &SiteDetails, new_details: SiteDetails) -> Result<SiteDetails>;
+
+    /// Permanently delete a site
+    fn delete_site(&self, site: &SiteDetails) -> Result<()>;
+
+    /// Deploy a built site
+    /// output_path: The directory the site was built into
+    fn deploy(&self, site: &SiteDetails, output_path: &Path) -> Result<Deployment>;
+
+    /// Deploy a built site as a preview, without replacing the live site
+    /// Not every provider supports this
+    /// Returns the deploy with the URL the preview can be seen at
+    fn preview(&self, _site: &SiteDetails, _output_path: &Path) -> Result<Deployment> {
+        Err(anyhow!("{} does not support preview deploys", self.name()))
+    }
+
+    /// Put a preview deploy live
+    /// Not every provider supports this
+    fn promote(&self, _site: &SiteDetails, _deploy_id: &str) -> Result<Deployment> {
+        Err(anyhow!("{} does not support preview deploys", self.name()))
+    }
+
+    /// Provision a custom SSL certificate for a site
+    /// Not every provider supports this
+    fn provision_ssl(&self, _site: &SiteDetails, _ssl_details: SslCert) -> Result<()> {
+        Err(anyhow!(
+            "{} does not support custom SSL certificates",
+            self.name()
+        ))
+    }
+
+    /// List a site's previous deploys, newest first
+    /// Not every provider keeps them
+    fn list_deploys(&self, _site: &SiteDetails) -> Result<Vec<DeployInfo>> {
+        Err(anyhow!("{} does not keep previous deploys", self.name()))
+    }
+
+    /// Put a previous deploy of a site live again
+    /// Not every provider supports this
+    fn rollback(&self, _site: &SiteDetails, _deploy_id: &str) -> Result<Deployment> {
+        Err(anyhow!(
+            "{} does not support rolling back to a previous deploy",
+            self.name()
+        ))
+    }
+}
+
+/// Site details for a site that only exists locally
+/// Local sites get a random ID, there is no host to hand one out
+/// Used by the providers that don't manage sites themselves
+pub fn new_local_site(name: &str) -> SiteDetails {
+    SiteDetails {
+        name: Some(name.to_string()),
+        id: Some(format!("{:016x}", rand::random::<u64>())),
+        ssl: Some(false),
+        url: None,
+        screenshot_url: None,
+    }
+}
+
+/// List the sites found in the local `sites` directory
+pub fn list_local_sites() -> Result<Vec<SiteDetails>> {
+    let sites_path = Path::new(SITES_DIR);
+    if !sites_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut sites = Vec::new();
+    for entry in fs::read_dir(sites_path).context("Failed to read the 'sites' directory")? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        // site directories are named `<name>_<id>`
+        let dir_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some((name, id)) = dir_name.rsplit_once('_') {
+            let mut site = new_local_site(name);
+            site.id = Some(id.to_string());
+            sites.push(site);
+        }
+    }
+    sites.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(sites)
+}
+
+/// Rename a local site's directory to match its new name
+pub fn rename_local_site(site: &SiteDetails, new_details: SiteDetails) -> Result<SiteDetails> {
+    let mut updated = site.clone();
+    updated.name = new_details.name.or(updated.name);
+
+    let old_path = site.build_site_path()?;
+    let new_path = updated.build_site_path()?;
+    if old_path != new_path && old_path.exists() {
+        fs::rename(&old_path, &new_path).context("Failed to rename the site directory")?;
+    }
+    Ok(updated)
+}
@@ -1,5 +1,5 @@
 pub mod cli;
-pub mod lib;
+pub mod credentials;
 pub mod netlify;
 pub mod crypto;
 
@@ -8,7 +8,8 @@ use dotenv::dotenv;
 // #[tokio::main]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
-    cli::draw_menu();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    cli::run(&args)?;
 
     // When initiating OAuth flow:
     //let (private_key, public_key) = generate_key_pair();
@@ -0,0 +1,172 @@
+/// Manifest Module
+/// Lists the files of a built site with their SHA1 hashes.
+/// Deploy targets compare a local manifest with the one they left behind last time,
+/// so only changed files are uploaded and deleted files are pruned.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Name of the manifest file deploy targets store next to the site's files
+pub const MANIFEST_FILE: &str = "driftwood-manifest.json";
+
+/// Files and directories that are never deployed, wherever they are in the tree
+pub const IGNORED: &[&str] = &[".git", "md_posts", ".DS_Store", "Thumbs.db", MANIFEST_FILE];
+
+/// Manifest struct
+/// files: The path of each file in `/path` form, mapped to its SHA1 hash
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, String>,
+}
+
+/// ManifestDiff struct
+/// What has to change on a host to match a build
+/// changed: Paths that are new or whose contents changed
+/// removed: Paths the host has that are gone from the build
+#[derive(Debug, Default)]
+pub struct ManifestDiff {
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Manifest {
+    /// Hash every file below a built site's directory, however deeply nested
+    /// Paths are normalised to `/path` form with forward slashes, ignored files are skipped
+    pub fn build(root: &Path) -> Result<Manifest> {
+        let mut manifest = Manifest::default();
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in
+                fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?
+            {
+                let path = entry?.path();
+                if Manifest::is_ignored(&path) {
+                    continue;
+                }
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.is_file() {
+                    let relative = path.strip_prefix(root)?;
+                    let url_path = relative
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    let contents = fs::read(&path)
+                        .with_context(|| format!("Failed to read {}", path.display()))?;
+                    manifest.files.insert(
+                        format!("/{}", url_path),
+                        sha1_smol::Sha1::from(&contents).digest().to_string(),
+                    );
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Whether a file or directory is left out of deploys
+    pub fn is_ignored(path: &Path) -> bool {
+        path.file_name()
+            .map(|name| IGNORED.iter().any(|ignored| name == *ignored))
+            .unwrap_or(false)
+    }
+
+    /// Read a manifest from its JSON form
+    pub fn from_json(json: &[u8]) -> Result<Manifest> {
+        serde_json::from_slice(json).context("Failed to parse the manifest")
+    }
+
+    /// The manifest in JSON form
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize the manifest")
+    }
+
+    /// The path on disk of a file in the manifest
+    /// root: The directory the manifest was built from
+    pub fn local_path(root: &Path, url_path: &str) -> PathBuf {
+        root.join(url_path.trim_start_matches('/'))
+    }
+
+    /// Compare this manifest with the one a host has
+    pub fn diff(&self, remote: &Manifest) -> ManifestDiff {
+        ManifestDiff {
+            changed: self
+                .files
+                .iter()
+                .filter(|(path, hash)| remote.files.get(*path) != Some(*hash))
+                .map(|(path, _)| path.clone())
+                .collect(),
+            removed: remote
+                .files
+                .keys()
+                .filter(|path| !self.files.contains_key(*path))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(files: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            files: files
+                .iter()
+                .map(|(path, hash)| (path.to_string(), hash.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff_lists_changed_and_removed_files() {
+        let local = manifest(&[
+            ("/index.html", "new"),
+            ("/posts/same.html", "same"),
+            ("/posts/added.html", "added"),
+        ]);
+        let remote = manifest(&[
+            ("/index.html", "old"),
+            ("/posts/same.html", "same"),
+            ("/posts/gone.html", "gone"),
+        ]);
+
+        let diff = local.diff(&remote);
+        assert_eq!(diff.changed, ["/index.html", "/posts/added.html"]);
+        assert_eq!(diff.removed, ["/posts/gone.html"]);
+    }
+
+    #[test]
+    fn diff_against_the_same_manifest_is_empty() {
+        let local = manifest(&[("/index.html", "hash")]);
+        let diff = local.diff(&local.clone());
+        assert!(diff.changed.is_empty());
+        assert!(diff.removed.is_empty());
+    }
+
+    #[test]
+    fn build_covers_nested_files_and_skips_ignored_ones() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("posts/2024")).unwrap();
+        fs::create_dir_all(root.path().join(".git")).unwrap();
+        fs::write(root.path().join("index.html"), "home").unwrap();
+        fs::write(root.path().join("posts/2024/deep.html"), "deep").unwrap();
+        fs::write(root.path().join(".git/HEAD"), "ref").unwrap();
+        fs::write(root.path().join(MANIFEST_FILE), "{}").unwrap();
+
+        let manifest = Manifest::build(root.path()).unwrap();
+        assert_eq!(
+            manifest.files.keys().collect::<Vec<_>>(),
+            ["/index.html", "/posts/2024/deep.html"]
+        );
+        assert_eq!(
+            Manifest::from_json(manifest.to_json().unwrap().as_bytes()).unwrap(),
+            manifest
+        );
+    }
+}
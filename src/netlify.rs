@@ -1,35 +1,53 @@
+use crate::credentials::{self, CredentialStore};
 use crate::crypto;
 /// TODO - Create a new server host to run the authentication logic through
-/// TODO - refresh token
 ///
+use anyhow::{bail, Context};
+use driftwood::history::{find_deploy, load_history, site_commit};
+use driftwood::hosting::{DeployInfo, Deployment, HostingProvider, SslCert};
+use driftwood::manifest::Manifest;
+use driftwood::upload::{upload_all, UploadError, UploadEvent, UploadOptions, UploadProgress};
 use driftwood::OAuth2;
 use driftwood::SiteDetails;
 use rsa::RsaPrivateKey;
 
-use oauth2::{
-    basic::BasicClient, reqwest::http_client, AuthUrl, AuthorizationCode, ClientId, ClientSecret,
-    CsrfToken, RedirectUrl, TokenResponse, TokenUrl,
-};
-use reqwest::Url;
 /// Netlify Module
 /// Used to interact with the Netlify API
-use serde::{Deserialize, Serialize};
-use std::io::Read;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::IsTerminal;
 use std::{
     collections::HashMap,
-    fs::{self, File},
-    io::{BufRead, BufReader, Write},
-    net::TcpListener,
+    fs::File,
     path::Path,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
 };
-use webbrowser;
+
+pub mod callback;
+pub mod error;
+pub mod models;
+pub mod oauth;
+pub mod rate_limit;
+pub mod token;
+use callback::CallbackServer;
+use error::NetlifyError;
+use models::{Deploy, DeployFile, Site, SiteSettings, SslCertificate, User};
+use oauth::{LoginFlow, RelayConfig};
+use rate_limit::RateLimiter;
+use token::{StoredToken, AUTH_TOKEN_VAR};
 
 /// Netlify struct
 /// Contains the user agent, token, and base URL for the Netlify API
+/// token: The access token, replaced when Netlify rejects it or it runs out
+/// credentials: Where the token is saved, and saved again when it's replaced
+/// rate_limiter: The API quota left, shared by every request
 pub struct Netlify {
     user_agent: String,
-    token: String,
+    token: Mutex<StoredToken>,
+    credentials: Box<dyn CredentialStore>,
     url: String,
+    rate_limiter: RateLimiter,
 }
 
 /// FileHashes struct
@@ -39,146 +57,457 @@ pub struct FileHashes {
     pub files: HashMap<String, String>,
 }
 
-/// Ssl_Cert struct
-/// Contains the details of an SSL certificate
-/// Fields match Netlify's API for provisioning an SSL certificate
-/// cert: The SSL certificate
-/// key: The SSL certificate key
-/// ca_cert: The SSL certificate CA
-pub struct Ssl_Cert {
-    pub cert: Option<String>,
-    pub key: Option<String>,
-    pub ca_cert: Option<String>,
+/// How long to wait for Netlify to finish processing a deploy,
+/// unless DRIFTWOOD_DEPLOY_TIMEOUT sets it in seconds
+const DEPLOY_TIMEOUT: Duration = Duration::from_secs(300);
+/// How many times a rate limited request is retried before the 429 is returned
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// How long to wait between checks of a deploy's state
+const DEPLOY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Check a deploy's state until it's ready, has failed, or the timeout has passed
+/// fetch: Gets the deploy as Netlify has it now
+/// elapsed: How long has been waited so far
+/// sleep: Waits between checks
+fn poll_deploy(
+    deploy_id: &str,
+    timeout: Duration,
+    mut fetch: impl FnMut() -> Result<Deploy, NetlifyError>,
+    elapsed: impl Fn() -> Duration,
+    mut sleep: impl FnMut(Duration),
+) -> Result<Deploy, NetlifyError> {
+    let mut last_state = String::new();
+    loop {
+        let deploy = fetch()?;
+        let state = deploy.state.clone().unwrap_or_default();
+        if state != last_state {
+            println!("> Deploy state: {}", state);
+            last_state = state.clone();
+        }
+
+        match state.as_str() {
+            "ready" => return Ok(deploy),
+            "error" => {
+                return Err(NetlifyError::DeployFailed {
+                    deploy_id: deploy_id.to_string(),
+                    message: deploy
+                        .error_message
+                        .unwrap_or_else(|| "Netlify did not say why".to_string()),
+                })
+            }
+            _ => {}
+        }
+
+        if elapsed() >= timeout {
+            return Err(NetlifyError::DeployTimeout {
+                deploy_id: deploy_id.to_string(),
+                state,
+                waited: timeout,
+            });
+        }
+        sleep(DEPLOY_POLL_INTERVAL);
+    }
+}
+
+/// NextStep enum
+/// What `send` does with a response
+/// Return: Hand the response to the caller
+/// Reauthenticate: Replace the token Netlify rejected, then send the request again if it can be
+/// WaitAndRetry: Wait out the rate limit, then send the request again
+#[derive(Debug, PartialEq)]
+enum NextStep {
+    Return,
+    Reauthenticate,
+    WaitAndRetry,
+}
+
+/// Decide what to do with a response to a request sent with the access token
+/// A 401 replaces the token once per request, a 429 is retried up to RATE_LIMIT_RETRIES times
+/// can_resend: Whether the request's body can be sent again
+/// reauthenticated: Whether the token was already replaced for this request
+/// attempt: How many times the request was retried after a 429
+fn next_step(
+    status: reqwest::StatusCode,
+    can_resend: bool,
+    reauthenticated: bool,
+    attempt: u32,
+) -> NextStep {
+    if status == reqwest::StatusCode::UNAUTHORIZED && !reauthenticated {
+        NextStep::Reauthenticate
+    } else if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        && attempt < RATE_LIMIT_RETRIES
+        && can_resend
+    {
+        NextStep::WaitAndRetry
+    } else {
+        NextStep::Return
+    }
+}
+
+/// The token to replace one that stopped working with
+/// A personal access token can't be renewed, any other token is refreshed,
+/// or when that isn't possible, replaced by logging in again
+/// refresh: Exchanges the token's refresh token for a new token
+/// log_in: Logs in through the browser
+fn renew_token(
+    token: &StoredToken,
+    refresh: impl FnOnce(&StoredToken) -> Result<StoredToken, Box<dyn std::error::Error>>,
+    log_in: impl FnOnce() -> Result<StoredToken, Box<dyn std::error::Error>>,
+) -> Result<StoredToken, NetlifyError> {
+    if token.personal {
+        println!("> The personal access token is no longer valid");
+        return Err(NetlifyError::AuthExpired);
+    }
+
+    match refresh(token) {
+        Ok(renewed) => Ok(renewed),
+        Err(e) => {
+            if token.refresh_token.is_some() {
+                println!("> Failed to refresh the token: {}", e);
+            }
+            println!("> Logging in to Netlify again");
+            log_in().map_err(|e| {
+                println!("> Failed to log in: {}", e);
+                NetlifyError::AuthExpired
+            })
+        }
+    }
+}
+
+impl HostingProvider for Netlify {
+    fn name(&self) -> &str {
+        "Netlify"
+    }
+
+    fn create_site(&self, name: &str) -> anyhow::Result<SiteDetails> {
+        Netlify::create_site(self, name)
+            .map(SiteDetails::from)
+            .map_err(to_anyhow)
+    }
+
+    fn list_sites(&self) -> anyhow::Result<Vec<SiteDetails>> {
+        let sites = self.get_sites().map_err(to_anyhow)?;
+        Ok(sites.into_iter().map(SiteDetails::from).collect())
+    }
+
+    fn update_site(
+        &self,
+        site: &SiteDetails,
+        new_details: SiteDetails,
+    ) -> anyhow::Result<SiteDetails> {
+        Netlify::update_site(self, site.clone(), new_details)
+            .map(SiteDetails::from)
+            .map_err(to_anyhow)
+    }
+
+    fn delete_site(&self, site: &SiteDetails) -> anyhow::Result<()> {
+        Netlify::delete_site(self, site.clone()).map_err(to_anyhow)
+    }
+
+    fn deploy(&self, site: &SiteDetails, output_path: &Path) -> anyhow::Result<Deployment> {
+        self.deploy_with_progress(site, output_path, false, print_upload_progress)
+    }
+
+    fn preview(&self, site: &SiteDetails, output_path: &Path) -> anyhow::Result<Deployment> {
+        self.deploy_with_progress(site, output_path, true, print_upload_progress)
+    }
+
+    fn promote(&self, site: &SiteDetails, deploy_id: &str) -> anyhow::Result<Deployment> {
+        let deploy = self.restore_deploy(site, deploy_id).map_err(to_anyhow)?;
+        Ok(Deployment {
+            id: deploy.id,
+            url: deploy.ssl_url.or(deploy.url),
+        })
+    }
+
+    /// Netlify's deploys, with the commit each was built from taken from the local history
+    /// and the live one taken from the site's published deploy
+    fn list_deploys(&self, site: &SiteDetails) -> anyhow::Result<Vec<DeployInfo>> {
+        let history = load_history(site)?;
+        let site_id = site.id.clone().context("The site has no ID")?;
+        let live_id = self
+            .get_site_details(&site_id)
+            .map_err(to_anyhow)?
+            .published_deploy
+            .and_then(|deploy| deploy.id);
+        let deploys = Netlify::list_deploys(self, site).map_err(to_anyhow)?;
+        Ok(deploys
+            .into_iter()
+            .filter_map(|deploy| {
+                let id = deploy.id?;
+                let record = find_deploy(&history, &id);
+                Some(DeployInfo {
+                    created_at: deploy.created_at,
+                    state: deploy.state,
+                    draft: deploy.draft.unwrap_or(false),
+                    live: live_id.as_deref() == Some(id.as_str()),
+                    message: record
+                        .as_ref()
+                        .and_then(|record| record.commit_message.clone())
+                        .or(deploy.title),
+                    commit: record.and_then(|record| record.commit),
+                    id,
+                })
+            })
+            .collect())
+    }
+
+    fn rollback(&self, site: &SiteDetails, deploy_id: &str) -> anyhow::Result<Deployment> {
+        let deploy = self.restore_deploy(site, deploy_id).map_err(to_anyhow)?;
+        Ok(Deployment {
+            id: deploy.id,
+            url: deploy.ssl_url.or(deploy.url),
+        })
+    }
+
+    fn provision_ssl(&self, site: &SiteDetails, ssl_details: SslCert) -> anyhow::Result<()> {
+        Netlify::provision_ssl(self, site.clone(), ssl_details)
+            .map(|certificate| println!("> Certificate state: {:?}", certificate.state))
+            .map_err(to_anyhow)
+    }
+}
+
+/// Print each uploaded, retried and failed file of a deploy
+fn print_upload_progress(event: &UploadEvent, progress: UploadProgress) {
+    let done = progress.uploaded + progress.failed;
+    match event {
+        UploadEvent::Started { .. } => {}
+        UploadEvent::Retrying {
+            path,
+            attempt,
+            delay,
+            error,
+        } => println!(
+            "> Retrying {} in {:?} (attempt {}): {}",
+            path, delay, attempt, error
+        ),
+        UploadEvent::Uploaded { path } => {
+            println!("> [{}/{}] Uploaded: {}", done, progress.total, path)
+        }
+        UploadEvent::Failed { path, error } => {
+            println!(
+                "> [{}/{}] Failed: {}: {}",
+                done, progress.total, path, error
+            )
+        }
+    }
+}
+
+/// How long to wait for a deploy to go live, from DRIFTWOOD_DEPLOY_TIMEOUT or the default
+fn deploy_timeout() -> Duration {
+    std::env::var("DRIFTWOOD_DEPLOY_TIMEOUT")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEPLOY_TIMEOUT)
+}
+
+/// Convert the errors the Netlify API calls return into anyhow errors
+/// The NetlifyError is kept, so callers can downcast to it and match on the case
+fn to_anyhow(e: NetlifyError) -> anyhow::Error {
+    anyhow::Error::new(e)
 }
 
 impl Netlify {
     /// Create a struct to store Netlify API connection details
-    /// checks for token file, if none exists, gets a new token from oauth2 flow
-    /// Returns a Netlify struct
-    pub fn new() -> Netlify {
+    /// Uses the token in NETLIFY_AUTH_TOKEN or the saved token, it never logs in by itself
+    /// Returns an error asking the user to log in when there is no token
+    pub fn connect() -> anyhow::Result<Netlify> {
         println!("> Creating Netlify API Struct");
 
+        Netlify::load()?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Not logged in to Netlify, run `driftwood login` or set {}",
+                AUTH_TOKEN_VAR
+            )
+        })
+    }
+
+    /// Connect with the token in NETLIFY_AUTH_TOKEN or the saved token, without logging in
+    /// Returns None when there is neither
+    pub fn load() -> anyhow::Result<Option<Netlify>> {
+        let credentials = credentials::default_store()?;
+        if let Some(token) = StoredToken::from_env() {
+            println!("> Using the token in {}", AUTH_TOKEN_VAR);
+            return Netlify::with_token(token, credentials).map(Some);
+        }
+        let token = StoredToken::load(credentials.as_ref())?;
+        token
+            .map(|token| Netlify::with_token(token, credentials))
+            .transpose()
+    }
+
+    /// Log in through the browser, replacing any saved token
+    pub fn log_in_and_save() -> anyhow::Result<Netlify> {
+        let credentials = credentials::default_store()?;
+        let token = Self::log_in().map_err(|e| anyhow::anyhow!("Failed to log in: {}", e))?;
+        token.save(credentials.as_ref())?;
+        println!("> Token saved to the {}", credentials.name());
+        Netlify::with_token(token, credentials)
+    }
+
+    /// Log in with a personal access token, without a browser
+    /// The token is checked with Netlify before it replaces any saved token
+    /// access_token: A personal access token from the Netlify dashboard
+    pub fn log_in_with_token(access_token: &str) -> anyhow::Result<Netlify> {
+        if access_token.trim().is_empty() {
+            bail!("The personal access token is empty");
+        }
+
+        let credentials = credentials::default_store()?;
+        let netlify = Netlify::with_token(StoredToken::personal(access_token), credentials)?;
+        netlify
+            .get_current_user()
+            .context("Netlify rejected the personal access token")?;
+
+        let token = netlify.token.lock().unwrap().clone();
+        token.save(netlify.credentials.as_ref())?;
+        println!("> Token saved to the {}", netlify.credentials.name());
+        Ok(netlify)
+    }
+
+    /// Remove the saved token, the next connection has to log in again
+    pub fn log_out() -> anyhow::Result<()> {
+        let credentials = credentials::default_store()?;
+        StoredToken::delete(credentials.as_ref())
+    }
+
+    /// Where the token came from, NETLIFY_AUTH_TOKEN or the store it's saved in
+    pub fn token_source(&self) -> String {
+        if StoredToken::from_env().is_some() {
+            format!("{} environment variable", AUTH_TOKEN_VAR)
+        } else {
+            self.credentials.name().to_string()
+        }
+    }
+
+    /// Connect to the API in NETLIFY_BASE_URL with a token
+    fn with_token(
+        token: StoredToken,
+        credentials: Box<dyn CredentialStore>,
+    ) -> anyhow::Result<Netlify> {
         // define the user agent
         let user_agent: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
         // define the base URL
-        let base_url: String = String::from(
-            OAuth2::get_env_var("NETLIFY_BASE_URL")
-                .expect("Failed to get NETLIFY_BASE_URL from .env file"),
-        );
+        let base_url: String = OAuth2::get_env_var("NETLIFY_BASE_URL")
+            .map_err(|e| anyhow::anyhow!("Failed to get NETLIFY_BASE_URL from .env file: {}", e))?;
+
+        Ok(Netlify {
+            user_agent: user_agent.to_string(),
+            token: Mutex::new(token),
+            credentials,
+            url: base_url,
+            rate_limiter: RateLimiter::default(),
+        })
+    }
 
-        // first check if there is a token on disk
-        // if not, get a new token
-        let token_file = Path::new("netlify_token.json");
-        if token_file.exists() {
-            println!("> Token file exists");
-            let token = fs::read_to_string(token_file).unwrap();
-            Netlify {
-                user_agent: user_agent.to_string(),
-                token: token,
-                url: base_url,
-            }
-        } else {
-            println!("> Token file does not exist");
-            let (code, state, private_key) = Self::login().expect("Failed to trigger login");
-            let token = Self::exchange_code_for_token(code, state, private_key)
-                .expect("Failed to exchange code for token");
-            fs::write(token_file, token.as_bytes()).unwrap();
-            Netlify {
-                user_agent: user_agent.to_string(),
-                token: token,
-                url: base_url,
+    /// Log in through the browser and get a new token
+    /// Scripts can't answer a browser, so without a terminal this fails instead
+    fn log_in() -> Result<StoredToken, Box<dyn std::error::Error>> {
+        if !std::io::stdin().is_terminal() {
+            return Err(format!(
+                "There's no terminal to log in from, set {} or run `driftwood login --with-token`",
+                AUTH_TOKEN_VAR
+            )
+            .into());
+        }
+        match LoginFlow::from_env() {
+            LoginFlow::Pkce(app) => app.log_in(),
+            LoginFlow::Relay(relay) => {
+                let (code, state, private_key) = Self::login(&relay)?;
+                Self::exchange_code_for_token(&relay, code, state, private_key)
             }
         }
     }
 
+    /// The access token to send, renewed first if it has run out
+    fn access_token(&self) -> Result<String, NetlifyError> {
+        let expired = {
+            let token = self.token.lock().unwrap();
+            token.is_expired().then(|| token.access_token.clone())
+        };
+        if let Some(expired) = expired {
+            println!("> The Netlify token has expired");
+            self.reauthenticate(&expired)?;
+        }
+        Ok(self.token.lock().unwrap().access_token.clone())
+    }
+
+    /// Replace a token that Netlify rejected or that has run out
+    /// Uses the refresh token if there is one, otherwise logs in again through the browser.
+    /// The lock is held throughout, so parallel uploads that hit the same 401 wait for one login.
+    /// rejected: The token that stopped working, nothing is done if it has already been replaced
+    fn reauthenticate(&self, rejected: &str) -> Result<(), NetlifyError> {
+        let mut token = self.token.lock().unwrap();
+        if token.access_token != rejected {
+            return Ok(());
+        }
+        let renewed = renew_token(&token, StoredToken::refresh, Self::log_in)?;
+        if let Err(e) = renewed.save(self.credentials.as_ref()) {
+            println!("> Failed to save the new token: {}", e);
+        }
+        *token = renewed;
+        Ok(())
+    }
+
     /// Get the details of a site
     /// id: The ID of the site
-    /// Returns a Result containing a vector of SiteDetails or an error
-    pub fn get_site_details(
-        &self,
-        id: &str,
-    ) -> Result<Vec<SiteDetails>, Box<dyn std::error::Error>> {
+    /// Returns a Result containing a Site or an error
+    pub fn get_site_details(&self, id: &str) -> Result<Site, NetlifyError> {
         println!("> Getting site details for: {}", id);
-
-        // create the url
-        let request_url = self.url.clone() + "sites/" + id;
-        // build and send the request
-        let client = self.build_client();
-        let response = self.send_get_request(client, request_url);
-        // return the response
-        self.read_array_response(response)
+        self.get(&format!("sites/{}", id))
     }
 
     /// Get all the sites for the user
-    /// Returns a Result containing a vector of SiteDetails or an error
-    pub fn get_sites(&self) -> Result<Vec<SiteDetails>, Box<dyn std::error::Error>> {
+    /// Returns a Result containing a vector of Sites or an error
+    pub fn get_sites(&self) -> Result<Vec<Site>, NetlifyError> {
         println!("> Getting all site details");
-
-        // create the url
-        let request_url = self.url.clone() + "sites";
-        // build and send the request
-        let client = self.build_client();
-        let response = self.send_get_request(client, request_url);
-        // return the response
-        self.read_array_response(response)
+        self.get("sites")
     }
 
     /// Add a new site
-    /// Returns a Result containing a vector of SiteDetails or an error
-    pub fn create_site(
-        &self,
-        new_site: SiteDetails,
-    ) -> Result<SiteDetails, Box<dyn std::error::Error>> {
-        println!("> Creating site: {}", new_site.name.clone().unwrap());
-
-        // create the url
-        let request_url = self.url.clone() + "sites";
-
-        // create the request body
-        let json = serde_json::to_value(new_site)?;
-
-        // build and send the request
-        let client = self.build_client();
-        let response = self.send_post_request(client, request_url, json);
-
-        // return the response
-        self.read_object_response(response)
+    /// name: The name of the new site
+    /// Returns a Result containing the new Site or an error
+    pub fn create_site(&self, name: &str) -> Result<Site, NetlifyError> {
+        println!("> Creating site: {}", name);
+
+        let settings = SiteSettings {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        self.post("sites", &settings)
+            .map_err(|e| e.or_name_conflict(name))
     }
 
     /// Update an existing site
-    /// Returns a Result containing a vector of the new SiteDetails or an error
+    /// Returns a Result containing the updated Site or an error
     pub fn update_site(
         &self,
         existing_site_details: SiteDetails,
         new_site_details: SiteDetails,
-    ) -> Result<SiteDetails, Box<dyn std::error::Error>> {
+    ) -> Result<Site, NetlifyError> {
         println!(
             "> Updating site: {}",
             existing_site_details.name.clone().unwrap()
         );
 
-        // create the url
-        let request_url =
-            self.url.clone() + "sites/" + existing_site_details.id.clone().unwrap().as_str();
-
-        // serialize the new_site_details into a serde_json::Value
-        let json = serde_json::to_value(new_site_details)?;
-
-        // build and send the request
-        let client = self.build_client();
-        let response = self.send_patch_request(client, request_url, json);
-
-        // return the response
-        self.read_object_response(response)
+        let name = new_site_details.name.unwrap_or_default();
+        let settings = SiteSettings {
+            name: Some(name.clone()),
+            ..Default::default()
+        };
+        self.patch(
+            &format!("sites/{}", existing_site_details.id.clone().unwrap()),
+            &settings,
+        )
+        .map_err(|e| e.or_name_conflict(&name))
     }
 
-    /// Update an existing site
-    /// Returns a Result containing a vector of the new SiteDetails or an error
-    pub fn delete_site(
-        &self,
-        site_details: SiteDetails,
-    ) -> Result<SiteDetails, Box<dyn std::error::Error>> {
+    /// Delete an existing site
+    /// Netlify answers with an empty body, so only the status is checked
+    /// Returns a Result containing nothing or an error
+    pub fn delete_site(&self, site_details: SiteDetails) -> Result<(), NetlifyError> {
         println!("> Deleting site: {}", site_details.name.clone().unwrap());
 
         // create the url
@@ -186,42 +515,121 @@ impl Netlify {
 
         // build and send the request
         let client = self.build_client();
-        let response = self.send_delete_request(client, request_url, serde_json::Value::Null);
+        let response = self.send_delete_request(client, request_url, serde_json::Value::Null)?;
 
-        // return the response
-        self.read_object_response(response)
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Netlify::read_error(response))
+        }
     }
 
     /// Send a list of files to the Netlify API
     /// site_details: A SiteDetails struct containing the site ID
     /// file_hashes: A FileHashes struct containing the path and SHA1 hash of a file
-    /// Returns a Result containing a vector of SiteDetails
-    /// with the checksums for the required files in a 'required' array
+    /// title: The deploy message shown in Netlify's deploy list
+    /// draft: Create a preview deploy that doesn't replace the live site
+    /// Returns a Result containing the new Deploy,
+    /// with the checksums for the required files in its 'required' array
     pub fn send_file_checksums(
         &self,
         site_details: SiteDetails,
         file_hashes: &FileHashes,
-    ) -> Result<SiteDetails, Box<dyn std::error::Error>> {
-        // create the url
-        let request_url = format!("{}sites/{}/deploys", self.url, site_details.id.unwrap());
+        title: Option<&str>,
+        draft: bool,
+    ) -> Result<Deploy, NetlifyError> {
+        let mut path = format!("sites/{}/deploys", site_details.id.unwrap());
+        if let Some(title) = title {
+            path = format!("{}?title={}", path, urlencoding::encode(title));
+        }
 
-        // build and send the request
-        let client = self.build_client();
-        let response =
-            self.send_post_request(client, request_url, serde_json::to_value(file_hashes)?);
+        let mut body = serde_json::to_value(file_hashes)?;
+        body["draft"] = serde_json::Value::Bool(draft);
+
+        self.post(&path, &body)
+    }
+
+    /// List a site's deploys, newest first
+    /// site_details: A SiteDetails struct containing the site ID
+    /// Returns a Result containing a vector of Deploys or an error
+    pub fn list_deploys(&self, site_details: &SiteDetails) -> Result<Vec<Deploy>, NetlifyError> {
+        println!(
+            "> Getting deploys for: {}",
+            site_details.name.clone().unwrap()
+        );
+        self.get(&format!(
+            "sites/{}/deploys",
+            site_details.id.clone().unwrap()
+        ))
+    }
+
+    /// Put a previous deploy of a site live again, or publish a draft deploy
+    /// site_details: A SiteDetails struct containing the site ID
+    /// deploy_id: The ID of the deploy to restore
+    /// Returns a Result containing the restored Deploy or an error
+    pub fn restore_deploy(
+        &self,
+        site_details: &SiteDetails,
+        deploy_id: &str,
+    ) -> Result<Deploy, NetlifyError> {
+        println!("> Restoring deploy: {}", deploy_id);
+        self.post(
+            &format!(
+                "sites/{}/deploys/{}/restore",
+                site_details.id.clone().unwrap(),
+                deploy_id
+            ),
+            &serde_json::json!({}),
+        )
+    }
 
-        // return the response
-        self.read_object_response(response)
+    /// Get the current state of a deploy
+    /// deploy_id: The ID of the deploy
+    /// Returns a Result containing a Deploy or an error
+    pub fn get_deploy(&self, deploy_id: &str) -> Result<Deploy, NetlifyError> {
+        self.get(&format!("deploys/{}", deploy_id))
+    }
+
+    /// Get the user the access token belongs to
+    /// Returns a Result containing a User or an error
+    pub fn get_current_user(&self) -> Result<User, NetlifyError> {
+        self.get("user")
+    }
+
+    /// Wait for Netlify to finish processing a deploy
+    /// deploy_id: The ID of the deploy
+    /// timeout: How long to wait before giving up
+    /// Returns the deploy once it's ready, or an error if it failed or took too long
+    pub fn wait_for_deploy(
+        &self,
+        deploy_id: &str,
+        timeout: Duration,
+    ) -> Result<Deploy, NetlifyError> {
+        println!("> Waiting for deploy {} to go live...", deploy_id);
+
+        let started = Instant::now();
+        poll_deploy(
+            deploy_id,
+            timeout,
+            || self.get_deploy(deploy_id),
+            || started.elapsed(),
+            thread::sleep,
+        )
     }
 
+    /// Upload a single file of a deploy
+    /// client: The client to send the request with, shared by every upload of the deploy
+    /// output_path: The directory the site was built into
+    /// deploy_id: The ID of the deploy the file belongs to
+    /// file_path: The path of the file in Netlify's `/path` form
+    /// Returns the uploaded DeployFile, or an UploadError saying whether the upload is worth retrying
     pub fn upload_file(
         &self,
-        // site_details: SiteDetails,
-        site_name: String,
-        site_id: String,
-        deploy_id: String,
+        client: &reqwest::blocking::Client,
+        output_path: &Path,
+        deploy_id: &str,
         file_path: &Path,
-    ) -> Result<SiteDetails, Box<dyn std::error::Error>> {
+    ) -> Result<DeployFile, UploadError> {
         // create the url
         let request_url = format!(
             "{}deploys/{}/files{}",
@@ -230,38 +638,41 @@ impl Netlify {
             file_path.display()
         );
 
-        println!("> request URL: {}", request_url);
-        println!("> File path: {}", file_path.display());
+        // the file path starts with a '/', which would replace output_path when joined
+        let full_path = output_path.join(file_path.strip_prefix("/").unwrap_or(file_path));
 
-        let full_path_str: String;
-        let mut full_path = Path::new("");
+        let file = File::open(&full_path)
+            .map_err(|e| UploadError::Fatal(format!("{}: {}", full_path.display(), e)))?;
 
-        // if the file is /index.html, full path will be sitename_siteid/index.html
-        if file_path.to_string_lossy() == "/index.html" {
-            println!("> File is index.html");
-            full_path_str = format!("sites/{}_{}/index.html", site_name, site_id);
-            full_path = Path::new(&full_path_str);
-        } else {
-            println!("> File is not index.html");
-            full_path_str = format!("sites/{}_{}{}", site_name, site_id, file_path.display());
-            full_path = Path::new(&full_path_str);
-        }
+        // send the request, only errors that can get better by trying again are retried
+        let response = self
+            .send_put_request(client.clone(), request_url, file)
+            .map_err(UploadError::from)?;
 
-        println!("> Full path: {}", full_path.display());
-
-        // confirm full_path exists
-        if !full_path.exists() {
-            return Err(format!("> {} not found", full_path.display()).into());
+        if response.status().is_success() {
+            return response
+                .json()
+                .map_err(|e| UploadError::Fatal(format!("Failed to read the response: {}", e)));
         }
 
-        let file = File::open(full_path)?;
-
-        // build and send the request
-        let client = self.build_client();
-        let response = self.send_put_request(client, request_url, file);
+        // the file couldn't be sent again with the new token, so it's retried from the start
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(UploadError::Retryable {
+                message: "Netlify rejected the token, retrying with a new one".to_string(),
+                retry_after: None,
+            });
+        }
 
-        // return the response
-        self.read_object_response(response)
+        let status = response.status().as_u16();
+        let retry_after = match response.status() {
+            reqwest::StatusCode::TOO_MANY_REQUESTS => Some(
+                self.rate_limiter
+                    .retry_delay(Netlify::retry_after(&response)),
+            ),
+            _ => Netlify::retry_after(&response),
+        };
+        let body = response.text().unwrap_or_default();
+        Err(UploadError::from_status(status, &body, retry_after))
     }
 
     /// Provision an SSL certificate for a site
@@ -271,15 +682,14 @@ impl Netlify {
     pub fn provision_ssl(
         &self,
         site_details: SiteDetails,
-        ssl_details: Ssl_Cert,
-    ) -> Result<bool, Box<dyn std::error::Error>> {
+        ssl_details: SslCert,
+    ) -> Result<SslCertificate, NetlifyError> {
         println!(
             "> Creating SSL certificate for: {}",
             site_details.name.clone().unwrap()
         );
 
-        let request_url = self.url.clone()
-            + "sites/"
+        let path = "sites/".to_string()
             + site_details.id.unwrap().as_str()
             + "/ssl?certificate="
             + ssl_details.cert.unwrap().as_str()
@@ -288,27 +698,8 @@ impl Netlify {
             + "&ca_certificates="
             + ssl_details.ca_cert.unwrap().as_str();
 
-        let client = self.build_client();
-
         // despite being a POST request, doesn't need a body.
-        let response = self.send_post_request(client, request_url, serde_json::Value::Null);
-
-        match response {
-            Ok(resp) => {
-                if resp.status().is_success() {
-                    let json: serde_json::Value = resp.json()?;
-                    println!("{}", json);
-                    return Ok(true);
-                } else {
-                    println!("> Request failed: {}", resp.status());
-                    return Err(format!("> Request failed: {}", resp.status()).into());
-                }
-            }
-            Err(e) => {
-                println!("> Request failed: {:?}", e);
-                return Err(format!("> Request failed: {:?}", e).into());
-            }
-        }
+        self.post(&path, &serde_json::Value::Null)
     }
 
     /// Create a reqwest::Client
@@ -330,12 +721,10 @@ impl Netlify {
         &self,
         client: reqwest::blocking::Client,
         request_url: String,
-    ) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    ) -> Result<reqwest::blocking::Response, NetlifyError> {
         println!("> Sending GET request to: {}", request_url);
 
-        let response = client.get(request_url).bearer_auth(&self.token).send()?;
-
-        Ok(response)
+        self.send(client.get(request_url))
     }
 
     /// Send a POST request to the Netlify API
@@ -348,18 +737,15 @@ impl Netlify {
         client: reqwest::blocking::Client,
         request_url: String,
         json: serde_json::Value,
-    ) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    ) -> Result<reqwest::blocking::Response, NetlifyError> {
         println!("> Sending POST request to: {}", request_url);
 
         let request = client
             .post(request_url)
-            .bearer_auth(&self.token)
             .json(&json)
             .headers(Netlify::build_request_headers());
 
-        let response = request.send()?;
-
-        Ok(response)
+        self.send(request)
     }
 
     /// Send a PUT request to the Netlify API
@@ -372,18 +758,15 @@ impl Netlify {
         client: reqwest::blocking::Client,
         request_url: String,
         file: File,
-    ) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    ) -> Result<reqwest::blocking::Response, NetlifyError> {
         println!("> Sending PUT request to: {}", request_url);
 
         let request = client
             .put(request_url)
-            .bearer_auth(&self.token)
             .body(file)
             .headers(Netlify::build_request_headers());
 
-        let response = request.send()?;
-
-        Ok(response)
+        self.send(request)
     }
 
     /// Send a PATCH request to the Netlify API
@@ -396,18 +779,15 @@ impl Netlify {
         client: reqwest::blocking::Client,
         request_url: String,
         json: serde_json::Value,
-    ) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    ) -> Result<reqwest::blocking::Response, NetlifyError> {
         println!("> Sending PATCH request to: {}", request_url);
 
         let request = client
             .patch(request_url)
-            .bearer_auth(&self.token)
             .json(&json)
             .headers(Netlify::build_request_headers());
 
-        let response = request.send()?;
-
-        Ok(response)
+        self.send(request)
     }
 
     /// Send a DELETE request to the Netlify API
@@ -420,86 +800,138 @@ impl Netlify {
         client: reqwest::blocking::Client,
         request_url: String,
         json: serde_json::Value,
-    ) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    ) -> Result<reqwest::blocking::Response, NetlifyError> {
         println!("> Sending DELETE request to: {}", request_url);
 
         let request = client
             .delete(request_url)
-            .bearer_auth(&self.token)
             .json(&json)
             .headers(Netlify::build_request_headers());
 
-        let response = request.send()?;
-
-        Ok(response)
+        self.send(request)
     }
 
-    /// Read the response from the Netlify API (array)
-    /// response: The response from the Netlify API
-    /// Returns a Result containing a vector of SiteDetails or an error
-    fn read_array_response(
+    /// Send a request with the access token, keeping within Netlify's rate limit
+    /// Waits when the quota is nearly used up, and retries a 429 after the wait Netlify asks for.
+    /// A 401 gets a new token and the request is retried once with it.
+    /// Requests whose body can't be sent twice, like file uploads, hand the 429 or 401 back to the caller.
+    fn send(
         &self,
-        response: Result<reqwest::blocking::Response, Box<dyn std::error::Error>>,
-    ) -> Result<Vec<SiteDetails>, Box<dyn std::error::Error>> {
-        println!("> Reading Response (array)...");
-
-        match response {
-            Ok(resp) => {
-                // println!("Response: {:?}", resp);
-
-                if resp.status().is_success() {
-                    let json: serde_json::Value = resp.json()?;
-                    let sites: Vec<SiteDetails> = serde_json::from_value(json)?;
-                    Ok(sites)
-                } else {
-                    println!("> Request failed: {}", resp.status());
-                    return Err(format!("> Request failed: {}", resp.status()).into());
+        mut request: reqwest::blocking::RequestBuilder,
+    ) -> Result<reqwest::blocking::Response, NetlifyError> {
+        let mut attempt = 0;
+        let mut reauthenticated = false;
+        loop {
+            self.rate_limiter.throttle();
+            let retry = request.try_clone();
+            let token = self.access_token()?;
+            let response = request.bearer_auth(&token).send()?;
+            self.rate_limiter.update(response.headers());
+
+            match next_step(response.status(), retry.is_some(), reauthenticated, attempt) {
+                NextStep::Return => return Ok(response),
+                NextStep::Reauthenticate => {
+                    println!("> Netlify rejected the token");
+                    self.reauthenticate(&token)?;
+                    reauthenticated = true;
+                    let Some(retry) = retry else {
+                        return Ok(response);
+                    };
+                    request = retry;
+                    continue;
                 }
+                NextStep::WaitAndRetry => {}
             }
-            Err(e) => {
-                println!("> Request failed: {:?}", e);
-                return Err(format!("> Request failed: {:?}", e).into());
-            }
+
+            let Some(retry) = retry else {
+                return Ok(response);
+            };
+            attempt += 1;
+            let delay = self
+                .rate_limiter
+                .retry_delay(Netlify::retry_after(&response));
+            println!(
+                "> Rate limited by Netlify, retrying in {}s (attempt {})",
+                delay.as_secs(),
+                attempt
+            );
+            thread::sleep(delay);
+            request = retry;
         }
     }
 
-    /// Read the response from the Netlify API (single object)
+    /// Send a GET request to an API path and read the response into a model
+    /// path: The path below the API's base URL, e.g. `sites`
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, NetlifyError> {
+        let client = self.build_client();
+        let response = self.send_get_request(client, self.url.clone() + path);
+        self.read_response(response)
+    }
+
+    /// Send a POST request to an API path and read the response into a model
+    /// path: The path below the API's base URL
+    /// body: The model to send as JSON
+    fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, NetlifyError> {
+        let client = self.build_client();
+        let response =
+            self.send_post_request(client, self.url.clone() + path, serde_json::to_value(body)?);
+        self.read_response(response)
+    }
+
+    /// Send a PATCH request to an API path and read the response into a model
+    /// path: The path below the API's base URL
+    /// body: The model to send as JSON
+    fn patch<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, NetlifyError> {
+        let client = self.build_client();
+        let response =
+            self.send_patch_request(client, self.url.clone() + path, serde_json::to_value(body)?);
+        self.read_response(response)
+    }
+
+    /// Read the response from the Netlify API into a model, or a vector of them
     /// response: The response from the Netlify API
-    /// Returns a Result containing a vector of SiteDetails or an error
-    fn read_object_response(
+    /// Returns a Result containing the model or an error
+    fn read_response<T: DeserializeOwned>(
         &self,
-        response: Result<reqwest::blocking::Response, Box<dyn std::error::Error>>,
-    ) -> Result<SiteDetails, Box<dyn std::error::Error>> {
-        println!("> Reading Response (object)...");
-
-        match response {
-            Ok(resp) => {
-                if resp.status() == 422 {
-                    println!("> Site name provided is not unique.");
-                    println!("> Request failed with a status of 422.");
-                    println!(concat!(
-                        "> !!!Note: 422 means 'unprocessable ",
-                        "entity', but it could just be your site name is already ",
-                        "being used. Try a different, more unique name.!!!"
-                    ));
-                }
+        response: Result<reqwest::blocking::Response, NetlifyError>,
+    ) -> Result<T, NetlifyError> {
+        println!("> Reading Response...");
 
-                if resp.status().is_success() {
-                    let json: serde_json::Value = resp.json()?;
-                    let sites: SiteDetails = serde_json::from_value(json)?;
-                    Ok(sites)
-                } else {
-                    println!("> Request failed: {}", resp.status());
-                    return Err(format!("> Request failed: {}", resp.status()).into());
-                }
-            }
-            Err(e) => {
-                println!("> Request failed: {:?}", e);
-                return Err(format!("> Request failed: {:?}", e).into());
-            }
+        let response = response?;
+        if response.status().is_success() {
+            Ok(response.json()?)
+        } else {
+            Err(Netlify::read_error(response))
         }
     }
 
+    /// Turn an unsuccessful response into the matching NetlifyError
+    fn read_error(response: reqwest::blocking::Response) -> NetlifyError {
+        println!("> Request failed: {}", response.status());
+        let status = response.status().as_u16();
+        let retry_after = Netlify::retry_after(&response);
+        let body = response.text().unwrap_or_default();
+        NetlifyError::from_response(status, retry_after, body)
+    }
+
+    /// How long a response asked us to wait before trying again, from its Retry-After header
+    fn retry_after(response: &reqwest::blocking::Response) -> Option<Duration> {
+        response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+    }
+
     /// Build the headers for the POST request, specifically create_site
     fn build_request_headers() -> reqwest::header::HeaderMap {
         let mut headers = reqwest::header::HeaderMap::new();
@@ -510,311 +942,329 @@ impl Netlify {
         headers
     }
 
-    /// Reads in all files in a site's posts directory and generates SHA1 hashes
-    /// Returns a FileHashes struct containing the path and SHA1 hash of a file
-    pub fn generate_sha1_for_posts(
-        site_path: &Path,
-        posts_dir: &Path,
-    ) -> Result<FileHashes, Box<dyn std::error::Error>> {
-        println!("> Generating SHA1 hashes for posts...");
-        println!("> Posts directory: {:?}", posts_dir);
+    /// Deploy a built site, reporting the progress of the uploads
+    /// Sends the checksums of every built file to Netlify, then uploads the files it doesn't
+    /// already have, a few at a time. The deploy only succeeds if every required file is uploaded
+    /// and Netlify finishes processing it.
+    /// draft: Create a preview deploy, the returned URL is then the deploy's own preview URL
+    /// progress: Called whenever a file starts, is retried, finishes or fails
+    pub fn deploy_with_progress<P>(
+        &self,
+        site: &SiteDetails,
+        output_path: &Path,
+        draft: bool,
+        progress: P,
+    ) -> anyhow::Result<Deployment>
+    where
+        P: Fn(&UploadEvent, UploadProgress) + Sync,
+    {
+        let file_hashes = Netlify::generate_file_hashes(output_path)?;
+        println!("> SHA1 hash generated successfully.");
+
+        // the deploy message links the deploy to the commit it was built from
+        let title =
+            site_commit(site).map(|commit| format!("{} ({})", commit.message, &commit.id[..7]));
+
+        // the response holds the deploy details, its id is the deploy's, not the site's
+        let deploy = self
+            .send_file_checksums(site.clone(), &file_hashes, title.as_deref(), draft)
+            .map_err(to_anyhow)?;
+        let deploy_id = deploy
+            .id
+            .clone()
+            .context("Netlify did not return a deploy ID")?;
+        let required = deploy.required.clone().unwrap_or_default();
+
+        // every file with a required hash is uploaded, files can share contents
+        let mut paths: Vec<String> = file_hashes
+            .files
+            .iter()
+            .filter(|(_, hash)| required.contains(hash))
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths.sort();
+        println!(
+            "> {} of {} files need uploading",
+            paths.len(),
+            file_hashes.files.len()
+        );
+
+        let client = self.build_client();
+        let report = upload_all(
+            &paths,
+            &UploadOptions::default(),
+            |path| {
+                self.upload_file(&client, output_path, &deploy_id, Path::new(path))
+                    .map(|_| ())
+            },
+            progress,
+        );
+        report
+            .into_result()
+            .with_context(|| format!("Deploy {} is incomplete", deploy_id))?;
 
-        let mut file_hashes = FileHashes {
-            files: HashMap::new(),
+        let deploy = self
+            .wait_for_deploy(&deploy_id, deploy_timeout())
+            .map_err(to_anyhow)?;
+
+        // a draft is only reachable at its own URL, the site's URL still serves the live deploy
+        let url = if draft {
+            deploy.deploy_ssl_url.or(deploy.deploy_url)
+        } else {
+            deploy.ssl_url.or(deploy.url)
         };
 
-        let mut sha1 = sha1_smol::Sha1::new();
+        Ok(Deployment {
+            id: Some(deploy_id),
+            url,
+        })
+    }
+
+    /// Generate the SHA1 hash of every file in a built site, including nested directories
+    /// output_path: The directory the site was built into
+    /// Returns a FileHashes struct containing the path and SHA1 hash of each file
+    pub fn generate_file_hashes(output_path: &Path) -> anyhow::Result<FileHashes> {
+        println!("> Generating SHA1 hashes for: {}", output_path.display());
 
         // ensure the index.html file exists
-        if !Path::new(&format!("{}/index.html", site_path.display())).exists() {
-            return Err(format!("> index.html not found in {}", site_path.display()).into());
+        if !output_path.join("index.html").exists() {
+            bail!("index.html not found in {}", output_path.display());
         }
 
-        // first grab the hash for /site/index.html
-        let index_file = fs::read(format!("{}/index.html", site_path.display()))?;
-
-        sha1.update(&index_file);
-        file_hashes
-            .files
-            .insert("/index.html".to_string(), sha1.digest().to_string());
-        sha1.reset();
-
-        // loop through the files in this dir
-        for entry in fs::read_dir(posts_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
-                // provide sha1.update with the file
-                let file = fs::read(path)?;
-                sha1.update(&file);
-                file_hashes
-                    .files
-                    .insert(format!("/posts/{}", file_name), sha1.digest().to_string());
-                sha1.reset();
-            }
-        }
+        let manifest = Manifest::build(output_path)?;
+        let file_hashes = FileHashes {
+            files: manifest.files.into_iter().collect(),
+        };
 
         println!("{:?}", file_hashes);
 
         Ok(file_hashes)
     }
 
-    pub fn login() -> Result<(String, String, rsa::RsaPrivateKey), Box<dyn std::error::Error>> {
+    /// Send the user to the relay's login page and wait for the browser to come back with a code
+    /// relay: Where the relay lives
+    /// Returns the authorization code, the state that came back with it, and the key the token
+    /// will be encrypted with
+    pub fn login(
+        relay: &RelayConfig,
+    ) -> Result<(String, String, rsa::RsaPrivateKey), Box<dyn std::error::Error>> {
         println!("> Logging in...");
 
         let (private_key, public_key) = crypto::generate_key_pair();
         let public_key_pem = crypto::get_public_key_pem(&public_key);
-        let encoded_public_key = urlencoding::encode(&public_key_pem);
 
-        let auth_url = format!(
-            "https://auth.driftwoodapp.com/login?public_key_pem={}",
-            encoded_public_key
+        // the relay sends the browser back to our callback server with our state,
+        // which is checked before the code is used
+        let server = CallbackServer::bind(callback::DEFAULT_PORT)?;
+        let auth_url = relay.authorize_url(
+            &public_key_pem,
+            server.state().secret(),
+            server.redirect_uri(),
         );
 
-        let listener = TcpListener::bind("127.0.0.1:8000")?;
-
         // Open the authorization URL in the user's browser
-        webbrowser::open(&auth_url)?;
-
-        for stream in listener.incoming() {
-            let mut stream = stream?;
-            let mut buffer = [0; 1024];
-            stream.read(&mut buffer)?;
-
-            let request = String::from_utf8_lossy(&buffer);
-            let url = request
-                .lines()
-                .next()
-                .unwrap()
-                .split_whitespace()
-                .nth(1)
-                .unwrap();
-            let url = Url::parse(&format!("http://localhost{}", url))?;
-
-            println!("> URL: {}", url);
-
-            let mut code = None;
-            let mut state = None;
-
-            for (key, value) in url.query_pairs() {
-                match key.as_ref() {
-                    "code" => code = Some(value.into()),
-                    "state" => state = Some(value.into_owned()),
-                    _ => {}
-                }
-            }
-
-            if let (Some(code), Some(state)) = (code, state) {
-                println!("> Authorization code: {}", code);
-                println!("> Authorization state: {}", state);
-
-                // Send "You can close this screen now" message
-                let file_contents = fs::read_to_string("src/templates/auth/code_received.html")
-                    .expect("Should have been able to read the file");
-
-                // Create the HTTP response
-                let response = format!(
-                    "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=UTF-8\r\nContent-Length: {}\r\n\r\n{}",
-                    file_contents.len(),
-                    file_contents
-                );
-
-                stream.write_all(response.as_bytes())?;
-                stream.flush()?;
-
-                return Ok((code, state, private_key));
-            }
-        }
+        oauth::open_browser(&auth_url);
 
-        Err("No authorization code received".into())
+        let callback = server.wait_for_code()?;
+        Ok((callback.code, callback.state, private_key))
     }
 
+    /// Exchange an authorization code for a token at the relay
+    /// The relay sends the token back encrypted with the public key the login was started with
+    /// relay: Where the relay lives
+    /// Returns the decrypted token
     pub fn exchange_code_for_token(
+        relay: &RelayConfig,
         code: String,
         state: String,
         private_key: RsaPrivateKey,
-    ) -> Result<String, Box<dyn std::error::Error>> {
+    ) -> Result<StoredToken, Box<dyn std::error::Error>> {
         println!("> Exchanging code for token...");
-        println!("> Code: {}", code);
 
         let client = reqwest::blocking::Client::new();
 
         let response = client
-            .get(format!(
-                "https://auth.driftwoodapp.com/callback?code={}&state={}",
-                code, state
-            ))
+            .get(relay.callback_url())
+            .query(&[("code", code.as_str()), ("state", state.as_str())])
             .send()?;
 
-        println!("> {:?}", response);
-
         let token_response: serde_json::Value = response.json()?;
 
-        println!("> Token response: {:?}", token_response);
-
-        let token: String = token_response["token"].as_str().unwrap().to_string();
-
-        println!("> Encrypted token: {}", token);
-
-        let token = crypto::decrypt_token(&token, &private_key);
-        println!("> Token: {}", token);
-        Ok(token)
-    }
-
-    // pub fn trigger_login() -> Result<(), Box<dyn std::error::Error>> {
-    //     println!("> Triggering login...");
-
-    //     let (private_key, public_key) = crypto::generate_key_pair();
-    //     let public_key_pem = crypto::get_public_key_pem(&public_key);
-    //     let encoded_public_key = urlencoding::encode(&public_key_pem);
-
-    //     let auth_url = format!("https://auth.driftwoodapp.com/login?public_key_pem={}", encoded_public_key);
-
-    //     println!("> sending request to: {}", auth_url);
-    //     webbrowser::open(&auth_url)?;
-
-    //     Ok(())
-    // }
-
-    // fn wait_for_response() -> Result<String, Box<dyn std::error::Error>> {
-    //     let listener = TcpListener::bind("localhost:8080")?;
-
-    //     for stream in listener.incoming() {
-    //         let mut stream = stream?;
-    //         let mut reader = BufReader::new(&stream);
-    //         let mut request_line = String::new();
-    //         reader.read_line(&mut request_line)?;
-
-    //         if request_line.starts_with("GET") {
-    //             let url = url::Url::parse(&format!("http://localhost{}", request_line.split_whitespace().nth(1).unwrap()))?;
-    //             let code = url.query_pairs()
-    //                 .find(|(key, _)| key == "code")
-    //                 .map(|(_, value)| value.into_owned())
-    //                 .ok_or("No code found in URL")?;
-
-    //             let auth_code = AuthorizationCode::new(code);
-
-    //             // Create the OAuth2 client (assuming it's already configured elsewhere)
-    //             let client = BasicClient::new(
-    //                 ClientId::new(OAuth2::get_env_var("NETLIFY_CLIENT_ID").unwrap()),
-    //                 Some(ClientSecret::new(OAuth2::get_env_var("NETLIFY_CLIENT_SECRET").unwrap())),
-    //                 AuthUrl::new("https://app.netlify.com/authorize".to_string()).unwrap(),
-    //                 Some(TokenUrl::new("https://api.netlify.com/oauth/token".to_string()).unwrap())
-    //             );
-
-    //             // Exchange the code for a token
-    //             let token_result = client
-    //                 .exchange_code(auth_code)
-    //                 .request(oauth2::reqwest::http_client)?;
-
-    //             // Return the access token as a string
-    //             return Ok(token_result.access_token().secret().to_string());
-    //         }
-    //     }
-
-    //     Err("Failed to receive response".into())
-    // }
-
-    // pub fn oauth2() -> Result<StandardTokenResponse<oauth2::EmptyExtraTokenFields, oauth2::basic::BasicTokenType>, Box<dyn Error>> {
-    pub fn netlify_oauth2_code_grant() -> Result<
-        oauth2::StandardTokenResponse<oauth2::EmptyExtraTokenFields, oauth2::basic::BasicTokenType>,
-        (),
-    > {
-        println!("> Starting Netlify OAuth2 flow");
-        let client = BasicClient::new(
-            ClientId::new(OAuth2::get_env_var("NETLIFY_CLIENT_ID").unwrap()),
-            // trying with no secret
-            // None,
-            Some(ClientSecret::new(
-                OAuth2::get_env_var("NETLIFY_CLIENT_SECRET").unwrap(),
-            )),
-            AuthUrl::new("https://app.netlify.com/authorize".to_string()).unwrap(),
-            Some(TokenUrl::new("https://api.netlify.com/oauth/token".to_string()).unwrap()),
-        )
-        .set_redirect_uri(
-            RedirectUrl::new(OAuth2::get_env_var("NETLIFY_REDIRECT_URI").unwrap()).unwrap(),
-        );
-
-        println!("> Generating PKCE challenge...");
+        let token = token_response["token"]
+            .as_str()
+            .ok_or("The login relay didn't return a token")?;
+        let token = crypto::decrypt_token(token, &private_key);
 
-        // Generate the authorization URL
-        let (auth_url, csrf_token) = client.authorize_url(CsrfToken::new_random).url();
+        // the relay passes on the expiry and refresh token when Netlify gives them
+        let expires_in = token_response["expires_in"]
+            .as_u64()
+            .map(Duration::from_secs);
+        let refresh_token = token_response["refresh_token"]
+            .as_str()
+            .map(|refresh_token| crypto::decrypt_token(refresh_token, &private_key));
 
-        println!("Open this URL in your browser:\n{auth_url}\n");
+        Ok(StoredToken::new(token, expires_in, refresh_token))
+    }
+}
 
-        let (code, state) = {
-            // A very naive implementation of the redirect server.
-            let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
 
-            // The server will terminate itself after collecting the first code.
-            let Some(mut stream) = listener.incoming().flatten().next() else {
-                panic!("listener terminated without accepting a connection");
-            };
-
-            let mut reader = BufReader::new(&stream);
+    #[test]
+    fn a_401_gets_a_new_token_once() {
+        assert_eq!(
+            next_step(StatusCode::UNAUTHORIZED, true, false, 0),
+            NextStep::Reauthenticate
+        );
+        // uploads can't be resent, but the token is still replaced for the next request
+        assert_eq!(
+            next_step(StatusCode::UNAUTHORIZED, false, false, 0),
+            NextStep::Reauthenticate
+        );
+        assert_eq!(
+            next_step(StatusCode::UNAUTHORIZED, true, true, 0),
+            NextStep::Return
+        );
+    }
 
-            let mut request_line = String::new();
-            reader.read_line(&mut request_line).unwrap();
+    #[test]
+    fn a_429_is_retried_until_the_retries_run_out() {
+        assert_eq!(
+            next_step(StatusCode::TOO_MANY_REQUESTS, true, false, 0),
+            NextStep::WaitAndRetry
+        );
+        assert_eq!(
+            next_step(
+                StatusCode::TOO_MANY_REQUESTS,
+                true,
+                true,
+                RATE_LIMIT_RETRIES - 1
+            ),
+            NextStep::WaitAndRetry
+        );
+        assert_eq!(
+            next_step(
+                StatusCode::TOO_MANY_REQUESTS,
+                true,
+                false,
+                RATE_LIMIT_RETRIES
+            ),
+            NextStep::Return
+        );
+        assert_eq!(
+            next_step(StatusCode::TOO_MANY_REQUESTS, false, false, 0),
+            NextStep::Return
+        );
+        assert_eq!(next_step(StatusCode::OK, true, false, 0), NextStep::Return);
+    }
 
-            println!("Request line: {:?}", request_line);
+    fn token(refresh_token: Option<&str>) -> StoredToken {
+        StoredToken::new("old".to_string(), None, refresh_token.map(String::from))
+    }
 
-            let redirect_url = request_line.split_whitespace().nth(1).unwrap();
+    fn issued(access_token: &str) -> Result<StoredToken, Box<dyn std::error::Error>> {
+        Ok(StoredToken::new(access_token.to_string(), None, None))
+    }
 
-            println!("Redirect URL: {:?}", redirect_url);
+    #[test]
+    fn tokens_are_refreshed_before_logging_in_again() {
+        let renewed = renew_token(
+            &token(Some("refresh")),
+            |_| issued("refreshed"),
+            || panic!("logged in although the token could be refreshed"),
+        )
+        .unwrap();
+        assert_eq!(renewed.access_token, "refreshed");
 
-            let url = Url::parse(&("http://localhost".to_string() + redirect_url)).unwrap();
+        let renewed = renew_token(
+            &token(None),
+            |_| Err("The token has no refresh token".into()),
+            || issued("logged-in"),
+        )
+        .unwrap();
+        assert_eq!(renewed.access_token, "logged-in");
+    }
 
-            println!("URL: {:?}", url.to_string().clone());
+    #[test]
+    fn a_failed_login_or_personal_token_means_logging_in_again() {
+        let failed = renew_token(
+            &token(Some("refresh")),
+            |_| Err("invalid_grant".into()),
+            || Err("no terminal".into()),
+        );
+        assert!(matches!(failed, Err(NetlifyError::AuthExpired)));
 
-            let code = url
-                .query_pairs()
-                .find(|(key, _)| key == "code")
-                .map(|(_, code)| AuthorizationCode::new(code.into_owned()))
-                .unwrap();
+        let personal = renew_token(
+            &StoredToken::personal("nfp_abc"),
+            |_| panic!("refreshed a personal access token"),
+            || panic!("logged in in place of a personal access token"),
+        );
+        assert!(matches!(personal, Err(NetlifyError::AuthExpired)));
+    }
 
-            let state = url
-                .query_pairs()
-                .find(|(key, _)| key == "state")
-                .map(|(_, state)| CsrfToken::new(state.into_owned()))
-                .unwrap();
+    /// Poll a deploy that goes through the given states, one per check, on a fake clock
+    /// Returns the outcome and the number of checks
+    fn poll(states: &[&str], error_message: Option<&str>) -> (Result<Deploy, NetlifyError>, usize) {
+        let checks = std::cell::Cell::new(0);
+        let waited = std::cell::Cell::new(Duration::ZERO);
+        let outcome = poll_deploy(
+            "d1",
+            Duration::from_secs(10),
+            || {
+                let state = states[checks.get().min(states.len() - 1)];
+                checks.set(checks.get() + 1);
+                Ok(Deploy {
+                    id: Some("d1".to_string()),
+                    state: Some(state.to_string()),
+                    error_message: error_message.map(String::from),
+                    ..Deploy::default()
+                })
+            },
+            || waited.get(),
+            |interval| waited.set(waited.get() + interval),
+        );
+        (outcome, checks.get())
+    }
 
-            let message = "Driftwood authorization complete! You can close this window now.";
-            let response = format!(
-                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
-                message.len(),
-                message
-            );
-            stream.write_all(response.as_bytes()).unwrap();
+    #[test]
+    fn waits_until_the_deploy_is_ready() {
+        let (outcome, checks) = poll(&["uploading", "processing", "ready"], None);
+        assert_eq!(outcome.unwrap().state.as_deref(), Some("ready"));
+        assert_eq!(checks, 3);
+    }
 
-            (code, state)
-        };
+    #[test]
+    fn a_failed_deploy_says_why() {
+        let (outcome, _) = poll(&["processing", "error"], Some("Build script returned 1"));
+        match outcome {
+            Err(NetlifyError::DeployFailed { deploy_id, message }) => {
+                assert_eq!(deploy_id, "d1");
+                assert_eq!(message, "Build script returned 1");
+            }
+            other => panic!("expected a failed deploy, got {:?}", other),
+        }
 
-        println!("Netlify code:\n{}\n", code.secret());
-        println!(
-            "Netlify state:\n{} (expected `{}`)\n",
-            state.secret(),
-            csrf_token.secret()
+        let (outcome, _) = poll(&["error"], None);
+        assert!(
+            matches!(outcome, Err(NetlifyError::DeployFailed { message, .. }) if message == "Netlify did not say why")
         );
+    }
 
-        // Exchange the authorization code for an access token
-        let token_result = client.exchange_code(code).request(http_client);
-
-        match token_result {
-            Ok(token_result) => {
-                println!(
-                    "Netlify Access token: {}",
-                    token_result.access_token().secret()
-                );
-                Ok(token_result)
-            }
-            Err(err) => {
-                println!("Failed to get token: {}", err);
-                if let oauth2::RequestTokenError::ServerResponse(body) = err {
-                    println!("Raw server response: {}", body);
-                }
-                Err(())
+    #[test]
+    fn gives_up_on_a_deploy_that_never_finishes() {
+        let (outcome, checks) = poll(&["processing"], None);
+        match outcome {
+            Err(NetlifyError::DeployTimeout { state, waited, .. }) => {
+                assert_eq!(state, "processing");
+                assert_eq!(waited, Duration::from_secs(10));
             }
+            other => panic!("expected a timeout, got {:?}", other),
         }
+        assert_eq!(
+            checks,
+            (Duration::from_secs(10).as_secs() / DEPLOY_POLL_INTERVAL.as_secs()) as usize + 1
+        );
     }
 }
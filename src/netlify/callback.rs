@@ -0,0 +1,402 @@
+/// Netlify Callback Module
+/// The localhost server the browser is sent back to at the end of a login.
+/// It only accepts a callback carrying the state it handed out, answers anything else with a 404,
+/// and gives up after a while so an abandoned login doesn't hang the app.
+use oauth2::CsrfToken;
+use reqwest::Url;
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The port the callback server listens on unless something else already is
+pub const DEFAULT_PORT: u16 = 8000;
+/// The path the browser is sent back to, unless a registered redirect URI says otherwise
+pub const CALLBACK_PATH: &str = "/callback";
+/// How long to wait for the login to finish, unless DRIFTWOOD_LOGIN_TIMEOUT sets it in seconds
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+/// How often to check for a connection while waiting
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a connection has to send its request before it's dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// The longest request line read, callbacks are far shorter
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+/// The page shown once the code has been received
+static CODE_RECEIVED_PAGE: &str = include_str!("../templates/auth/code_received.html");
+
+/// CallbackError enum
+/// Denied: The user or the provider refused the login, with the provider's error code and description
+/// TimedOut: Nobody came back to the callback in time
+/// Io: The server couldn't be started or stopped accepting connections
+#[derive(Debug)]
+pub enum CallbackError {
+    Denied {
+        error: String,
+        description: Option<String>,
+    },
+    TimedOut(Duration),
+    Io(io::Error),
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallbackError::Denied {
+                error,
+                description: Some(description),
+            } => write!(f, "The login was refused ({}): {}", error, description),
+            CallbackError::Denied {
+                error,
+                description: None,
+            } => write!(f, "The login was refused: {}", error),
+            CallbackError::TimedOut(waited) => write!(
+                f,
+                "The login wasn't finished within {} seconds",
+                waited.as_secs()
+            ),
+            CallbackError::Io(e) => write!(f, "The login callback server failed: {}", e),
+        }
+    }
+}
+
+impl Error for CallbackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CallbackError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CallbackError {
+    fn from(e: io::Error) -> CallbackError {
+        CallbackError::Io(e)
+    }
+}
+
+/// Callback struct
+/// What the browser brought back from a successful login
+/// code: The authorization code
+/// state: The state that came with it
+#[derive(Debug, Clone)]
+pub struct Callback {
+    pub code: String,
+    pub state: String,
+}
+
+/// CallbackServer struct
+/// A one-shot server on 127.0.0.1 waiting for the browser to bring back an authorization code
+/// listener: The socket the browser connects to
+/// redirect_uri: The URL the browser is sent back to
+/// path: The path of the redirect URI, requests for any other path are ignored
+/// state: The random CSRF state sent with the login, the callback has to return it unchanged
+/// timeout: How long to wait for the callback
+pub struct CallbackServer {
+    listener: TcpListener,
+    redirect_uri: String,
+    path: String,
+    state: CsrfToken,
+    timeout: Duration,
+}
+
+impl CallbackServer {
+    /// Start listening on the preferred port, or on any free port if it's taken
+    /// preferred_port: The port to try first
+    pub fn bind(preferred_port: u16) -> Result<CallbackServer, CallbackError> {
+        let listener = match TcpListener::bind(("127.0.0.1", preferred_port)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                println!(
+                    "> Port {} is unavailable ({}), using a free port",
+                    preferred_port, e
+                );
+                TcpListener::bind(("127.0.0.1", 0))?
+            }
+        };
+        let port = listener.local_addr()?.port();
+        let redirect_uri = format!("http://127.0.0.1:{}{}", port, CALLBACK_PATH);
+        CallbackServer::start(listener, redirect_uri, CALLBACK_PATH.to_string())
+    }
+
+    /// Start listening for a redirect URI registered with an OAuth app
+    /// The URI can't be changed, so its port has to be free
+    /// redirect_uri: A `http://localhost` or `http://127.0.0.1` URL
+    pub fn bind_redirect_uri(redirect_uri: &Url) -> Result<CallbackServer, CallbackError> {
+        let local = matches!(redirect_uri.host_str(), Some("localhost" | "127.0.0.1"));
+        let port = match redirect_uri.port_or_known_default() {
+            Some(port) if local && redirect_uri.scheme() == "http" => port,
+            _ => {
+                return Err(CallbackError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} isn't a http://localhost URL", redirect_uri),
+                )))
+            }
+        };
+
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        CallbackServer::start(
+            listener,
+            redirect_uri.to_string(),
+            redirect_uri.path().to_string(),
+        )
+    }
+
+    /// Set up a server on a bound socket, with a fresh state for this login
+    fn start(
+        listener: TcpListener,
+        redirect_uri: String,
+        path: String,
+    ) -> Result<CallbackServer, CallbackError> {
+        listener.set_nonblocking(true)?;
+        Ok(CallbackServer {
+            listener,
+            redirect_uri,
+            path,
+            state: CsrfToken::new_random(),
+            timeout: login_timeout(),
+        })
+    }
+
+    /// The URL the provider should send the browser back to
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// The CSRF state to send with the login
+    pub fn state(&self) -> &CsrfToken {
+        &self.state
+    }
+
+    /// Wait for the browser to come back with an authorization code
+    /// Requests for other paths and callbacks with the wrong state are answered and ignored.
+    /// Returns the code and state, or an error if the login was refused or took too long
+    pub fn wait_for_code(&self) -> Result<Callback, CallbackError> {
+        println!("> Waiting for the login to finish in the browser...");
+
+        let started = Instant::now();
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if started.elapsed() >= self.timeout {
+                        return Err(CallbackError::TimedOut(self.timeout));
+                    }
+                    thread::sleep(ACCEPT_INTERVAL);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            // a connection that misbehaves is dropped, it doesn't end the login
+            match self.handle(stream) {
+                Ok(Some(result)) => return result,
+                Ok(None) => {}
+                Err(e) => println!("> Ignored a bad request to the callback server: {}", e),
+            }
+        }
+    }
+
+    /// Answer one connection
+    /// Returns the outcome of the login if this was the callback, None for anything else
+    fn handle(&self, mut stream: TcpStream) -> io::Result<Option<Result<Callback, CallbackError>>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        let mut request_line = String::new();
+        BufReader::new((&stream).take(MAX_REQUEST_LINE)).read_line(&mut request_line)?;
+
+        let mut parts = request_line.split_whitespace();
+        let (Some("GET"), Some(target)) = (parts.next(), parts.next()) else {
+            respond(&mut stream, "400 Bad Request", "text/plain", "Bad request")?;
+            return Ok(None);
+        };
+        let Ok(url) = Url::parse(&format!("http://127.0.0.1{}", target)) else {
+            respond(&mut stream, "400 Bad Request", "text/plain", "Bad request")?;
+            return Ok(None);
+        };
+        if url.path() != self.path {
+            respond(&mut stream, "404 Not Found", "text/plain", "Not found")?;
+            return Ok(None);
+        }
+
+        let query = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+
+        // anyone can reach the callback, only the one carrying our state is trusted
+        let state = query("state");
+        if state.as_deref() != Some(self.state.secret().as_str()) {
+            println!("> Ignored a login callback with the wrong state");
+            respond(
+                &mut stream,
+                "400 Bad Request",
+                "text/plain",
+                "This login link doesn't match the login in progress.",
+            )?;
+            return Ok(None);
+        }
+
+        if let Some(error) = query("error") {
+            respond(
+                &mut stream,
+                "200 OK",
+                "text/plain",
+                "The login was refused. You can close this page and try again.",
+            )?;
+            return Ok(Some(Err(CallbackError::Denied {
+                error,
+                description: query("error_description"),
+            })));
+        }
+
+        let (Some(code), Some(state)) = (query("code"), state) else {
+            respond(
+                &mut stream,
+                "400 Bad Request",
+                "text/plain",
+                "No authorization code",
+            )?;
+            return Ok(None);
+        };
+
+        println!("> Authorization code received");
+        respond(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=UTF-8",
+            CODE_RECEIVED_PAGE,
+        )?;
+        Ok(Some(Ok(Callback { code, state })))
+    }
+}
+
+/// Write a complete HTTP response and close the connection
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+/// How long to wait for a login, from DRIFTWOOD_LOGIN_TIMEOUT or the default
+fn login_timeout() -> Duration {
+    std::env::var("DRIFTWOOD_LOGIN_TIMEOUT")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(LOGIN_TIMEOUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a GET request to the server and return the response's status line
+    fn get(server: &CallbackServer, target: &str) -> String {
+        let port = server.listener.local_addr().unwrap().port();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", target).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    /// A server on a free port with a short timeout
+    fn server() -> CallbackServer {
+        let mut server = CallbackServer::bind(0).unwrap();
+        server.timeout = Duration::from_secs(10);
+        server
+    }
+
+    #[test]
+    fn ignores_other_paths_and_the_wrong_state() {
+        let server = server();
+        let state = server.state().secret().clone();
+
+        let callback = thread::scope(|scope| {
+            let waiting = scope.spawn(|| server.wait_for_code());
+            assert_eq!(get(&server, "/favicon.ico"), "HTTP/1.1 404 Not Found");
+            assert_eq!(
+                get(&server, &format!("/elsewhere?code=abc&state={}", state)),
+                "HTTP/1.1 404 Not Found"
+            );
+            assert_eq!(
+                get(&server, "/callback?code=abc&state=forged"),
+                "HTTP/1.1 400 Bad Request"
+            );
+            assert_eq!(
+                get(&server, "/callback?code=abc"),
+                "HTTP/1.1 400 Bad Request"
+            );
+            assert_eq!(
+                get(&server, &format!("/callback?code=abc&state={}", state)),
+                "HTTP/1.1 200 OK"
+            );
+            waiting.join().unwrap()
+        })
+        .unwrap();
+
+        assert_eq!(callback.code, "abc");
+        assert_eq!(callback.state, state);
+    }
+
+    #[test]
+    fn reports_a_refused_login() {
+        let server = server();
+        let state = server.state().secret().clone();
+
+        let result = thread::scope(|scope| {
+            let waiting = scope.spawn(|| server.wait_for_code());
+            get(
+                &server,
+                &format!("/callback?error=access_denied&state={}", state),
+            );
+            waiting.join().unwrap()
+        });
+
+        assert!(matches!(
+            result,
+            Err(CallbackError::Denied { error, .. }) if error == "access_denied"
+        ));
+    }
+
+    #[test]
+    fn gives_up_when_nobody_comes_back() {
+        let mut server = server();
+        server.timeout = Duration::from_millis(200);
+        assert!(matches!(
+            server.wait_for_code(),
+            Err(CallbackError::TimedOut(_))
+        ));
+    }
+
+    #[test]
+    fn falls_back_to_a_free_port_when_the_preferred_one_is_taken() {
+        let taken = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let server = CallbackServer::bind(port).unwrap();
+        let bound = server.listener.local_addr().unwrap().port();
+        assert_ne!(bound, port);
+        assert_eq!(
+            server.redirect_uri(),
+            format!("http://127.0.0.1:{}{}", bound, CALLBACK_PATH)
+        );
+    }
+
+    #[test]
+    fn every_server_has_its_own_state() {
+        assert_ne!(server().state().secret(), server().state().secret());
+    }
+}
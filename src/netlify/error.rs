@@ -0,0 +1,235 @@
+/// Netlify Error Module
+/// The ways a call to the Netlify API can fail.
+/// Callers match on these to react, e.g. asking for another name when a site name is taken.
+use driftwood::upload::UploadError;
+use serde::Deserialize;
+use std::{error::Error, fmt, time::Duration};
+
+/// NetlifyError enum
+/// Http: Any other unsuccessful response, with the error Netlify sent back
+/// AuthExpired: The access token was rejected, the user has to log in again
+/// NameConflict: A site with the requested name already exists
+/// RateLimited: Too many requests, with how long Netlify asked us to wait
+/// DeployFailed: Netlify could not process a deploy
+/// DeployTimeout: A deploy didn't finish processing in time
+/// Network: The request never got a response
+/// Deserialization: The response wasn't what the models expect
+#[derive(Debug)]
+pub enum NetlifyError {
+    Http {
+        status: u16,
+        body: ApiErrorBody,
+    },
+    AuthExpired,
+    NameConflict {
+        name: String,
+    },
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    DeployFailed {
+        deploy_id: String,
+        message: String,
+    },
+    DeployTimeout {
+        deploy_id: String,
+        state: String,
+        waited: Duration,
+    },
+    Network(reqwest::Error),
+    Deserialization(String),
+}
+
+/// ApiErrorBody struct
+/// The body Netlify sends with an unsuccessful response
+/// code: The status code, repeated in the body
+/// message: What went wrong
+/// errors: Validation errors, keyed by field
+/// raw: The body as it was sent, for responses that aren't JSON
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ApiErrorBody {
+    pub code: Option<u16>,
+    pub message: Option<String>,
+    pub errors: Option<serde_json::Value>,
+    #[serde(skip)]
+    pub raw: String,
+}
+
+impl ApiErrorBody {
+    /// Parse the body of an unsuccessful response, keeping the raw text when it isn't JSON
+    pub fn parse(raw: String) -> ApiErrorBody {
+        let body: ApiErrorBody = serde_json::from_str(&raw).unwrap_or_default();
+        ApiErrorBody { raw, ..body }
+    }
+}
+
+impl fmt::Display for ApiErrorBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.message, &self.errors) {
+            (Some(message), _) => write!(f, "{}", message),
+            (None, Some(errors)) => write!(f, "{}", errors),
+            (None, None) => write!(f, "{}", self.raw),
+        }
+    }
+}
+
+impl NetlifyError {
+    /// Classify an unsuccessful response
+    /// status: The status code of the response
+    /// retry_after: The response's Retry-After header, in seconds
+    /// body: The body of the response
+    pub fn from_response(status: u16, retry_after: Option<Duration>, body: String) -> NetlifyError {
+        match status {
+            401 => NetlifyError::AuthExpired,
+            429 => NetlifyError::RateLimited { retry_after },
+            _ => NetlifyError::Http {
+                status,
+                body: ApiErrorBody::parse(body),
+            },
+        }
+    }
+
+    /// Treat a 422 as the site name being taken, the only thing Netlify rejects a site's name for
+    /// name: The name that was sent
+    pub fn or_name_conflict(self, name: &str) -> NetlifyError {
+        match self {
+            NetlifyError::Http { status: 422, .. } => NetlifyError::NameConflict {
+                name: name.to_string(),
+            },
+            other => other,
+        }
+    }
+
+    /// The HTTP status of the response, for errors that had one
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            NetlifyError::Http { status, .. } => Some(*status),
+            NetlifyError::AuthExpired => Some(401),
+            NetlifyError::NameConflict { .. } => Some(422),
+            NetlifyError::RateLimited { .. } => Some(429),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for NetlifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetlifyError::Http { status, body } => {
+                write!(f, "Netlify returned {}: {}", status, body)
+            }
+            NetlifyError::AuthExpired => {
+                write!(f, "The Netlify login has expired, log in again")
+            }
+            NetlifyError::NameConflict { name } => {
+                write!(f, "The site name '{}' is already taken", name)
+            }
+            NetlifyError::RateLimited {
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "Rate limited by Netlify, try again in {} seconds",
+                retry_after.as_secs()
+            ),
+            NetlifyError::RateLimited { retry_after: None } => {
+                write!(f, "Rate limited by Netlify, try again later")
+            }
+            NetlifyError::DeployFailed { deploy_id, message } => {
+                write!(f, "Deploy {} failed: {}", deploy_id, message)
+            }
+            NetlifyError::DeployTimeout {
+                deploy_id,
+                state,
+                waited,
+            } => write!(
+                f,
+                "Deploy {} was still '{}' after {} seconds",
+                deploy_id,
+                state,
+                waited.as_secs()
+            ),
+            NetlifyError::Network(e) => write!(f, "Network error: {}", e),
+            NetlifyError::Deserialization(message) => {
+                write!(f, "Unexpected response from Netlify: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for NetlifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetlifyError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for NetlifyError {
+    fn from(e: reqwest::Error) -> NetlifyError {
+        if e.is_decode() {
+            NetlifyError::Deserialization(e.to_string())
+        } else {
+            NetlifyError::Network(e)
+        }
+    }
+}
+
+impl From<serde_json::Error> for NetlifyError {
+    fn from(e: serde_json::Error) -> NetlifyError {
+        NetlifyError::Deserialization(e.to_string())
+    }
+}
+
+impl From<NetlifyError> for UploadError {
+    /// Only what can get better by trying again is retried: requests that never got an answer,
+    /// rate limits and server errors. A response that can't be read won't read better next time.
+    fn from(e: NetlifyError) -> UploadError {
+        match e {
+            NetlifyError::Network(e) if !e.is_builder() => UploadError::network(e),
+            NetlifyError::RateLimited { retry_after } => {
+                UploadError::from_status(429, "Rate limited by Netlify", retry_after)
+            }
+            NetlifyError::Http { status, body } => {
+                UploadError::from_status(status, &body.to_string(), None)
+            }
+            e => UploadError::Fatal(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_retryable(e: NetlifyError) -> bool {
+        matches!(UploadError::from(e), UploadError::Retryable { .. })
+    }
+
+    #[test]
+    fn only_rate_limits_and_server_errors_are_retried() {
+        assert!(is_retryable(NetlifyError::RateLimited {
+            retry_after: None
+        }));
+        assert!(is_retryable(NetlifyError::from_response(
+            502,
+            None,
+            String::new()
+        )));
+        assert!(!is_retryable(NetlifyError::from_response(
+            404,
+            None,
+            String::new()
+        )));
+        assert!(!is_retryable(NetlifyError::AuthExpired));
+    }
+
+    #[test]
+    fn responses_that_cant_be_read_are_not_retried() {
+        let e = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert!(!is_retryable(NetlifyError::from(e)));
+        assert!(!is_retryable(NetlifyError::Deserialization(
+            "missing field `id`".to_string()
+        )));
+    }
+}
@@ -0,0 +1,269 @@
+/// Netlify Models Module
+/// The shapes of the objects the Netlify API sends and receives.
+/// Every field is optional, Netlify leaves out whatever doesn't apply, and fields
+/// the app doesn't use are ignored when deserializing.
+use driftwood::SiteDetails;
+use serde::{Deserialize, Serialize};
+
+/// Site struct
+/// A site, as Netlify reports it
+/// id: The ID of the site
+/// name: The site's name, which is also its netlify.app subdomain
+/// url: The site's main URL
+/// ssl_url: The site's main URL over HTTPS
+/// admin_url: The site's page in the Netlify dashboard
+/// custom_domain: The site's own domain, if it has one
+/// ssl: Whether the site is served over HTTPS
+/// screenshot_url: The URL of a screenshot of the site
+/// published_deploy: The deploy the site is serving
+/// created_at: When the site was created
+/// updated_at: When the site was last changed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Site {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub ssl_url: Option<String>,
+    pub admin_url: Option<String>,
+    pub custom_domain: Option<String>,
+    pub ssl: Option<bool>,
+    pub screenshot_url: Option<String>,
+    pub published_deploy: Option<Deploy>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+impl From<Site> for SiteDetails {
+    /// The HTTPS URL is kept when Netlify has one, so feeds and the sitemap link to it
+    fn from(site: Site) -> SiteDetails {
+        SiteDetails {
+            name: site.name,
+            id: site.id,
+            ssl: site.ssl,
+            url: site.ssl_url.filter(|url| !url.is_empty()).or(site.url),
+            screenshot_url: site.screenshot_url,
+        }
+    }
+}
+
+/// SiteSettings struct
+/// The settings sent when creating or updating a site, unset fields are left as they are
+/// name: The site's name
+/// custom_domain: The site's own domain
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SiteSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_domain: Option<String>,
+}
+
+/// Deploy struct
+/// The state of a deploy, as Netlify reports it
+/// id: The ID of the deploy
+/// site_id: The ID of the site the deploy belongs to
+/// state: Where the deploy is up to, e.g. `uploading`, `processing`, `ready` or `error`
+/// created_at: When the deploy was created
+/// title: The deploy message, the message of the site commit it was built from
+/// draft: Whether the deploy is a preview that hasn't been published
+/// required: The SHA1 hashes of the files Netlify doesn't have yet
+/// error_message: Why the deploy failed, when its state is `error`
+/// url: The site's main URL
+/// ssl_url: The site's main URL over HTTPS
+/// deploy_url: The permanent URL of this deploy
+/// deploy_ssl_url: The permanent URL of this deploy over HTTPS
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Deploy {
+    pub id: Option<String>,
+    pub site_id: Option<String>,
+    pub state: Option<String>,
+    pub created_at: Option<String>,
+    pub title: Option<String>,
+    pub draft: Option<bool>,
+    pub required: Option<Vec<String>>,
+    pub error_message: Option<String>,
+    pub url: Option<String>,
+    pub ssl_url: Option<String>,
+    pub deploy_url: Option<String>,
+    pub deploy_ssl_url: Option<String>,
+}
+
+/// DeployFile struct
+/// A file that has been uploaded to a deploy
+/// id: The path of the file, Netlify uses it as the file's ID
+/// path: The path of the file in `/path` form
+/// sha: The SHA1 hash of the file
+/// mime_type: The content type the file is served with
+/// size: The size of the file in bytes
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DeployFile {
+    pub id: Option<String>,
+    pub path: Option<String>,
+    pub sha: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: Option<u64>,
+}
+
+/// SslCertificate struct
+/// The SSL certificate a site is served with
+/// state: Where provisioning is up to, e.g. `issued`
+/// domains: The domains the certificate covers
+/// created_at: When the certificate was created
+/// expires_at: When the certificate runs out
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SslCertificate {
+    pub state: Option<String>,
+    pub domains: Option<Vec<String>>,
+    pub created_at: Option<String>,
+    pub expires_at: Option<String>,
+}
+
+/// User struct
+/// The user the access token belongs to
+/// id: The ID of the user
+/// email: The user's email address
+/// full_name: The user's name
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct User {
+    pub id: Option<String>,
+    pub email: Option<String>,
+    pub full_name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_site_is_read_from_netlifys_json() {
+        let site: Site = serde_json::from_str(
+            r#"{
+                "id": "3970e0fe-8564-4903-9a55-c5f8de49fb8b",
+                "name": "driftwood-blog",
+                "url": "http://driftwood-blog.netlify.app",
+                "ssl_url": "https://driftwood-blog.netlify.app",
+                "admin_url": "https://app.netlify.com/sites/driftwood-blog",
+                "custom_domain": null,
+                "ssl": true,
+                "screenshot_url": null,
+                "published_deploy": {
+                    "id": "6626e3a1d1e5c90008a1b2c3",
+                    "state": "ready",
+                    "draft": false,
+                    "created_at": "2024-04-22T22:30:25.540Z"
+                },
+                "created_at": "2024-01-01T00:00:00.000Z",
+                "updated_at": "2024-04-22T22:30:30.000Z",
+                "build_settings": {"cmd": ""},
+                "capabilities": {"title": "Starter"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(site.name.as_deref(), Some("driftwood-blog"));
+        assert_eq!(site.ssl, Some(true));
+        assert_eq!(site.custom_domain, None);
+        let published = site.published_deploy.unwrap();
+        assert_eq!(published.id.as_deref(), Some("6626e3a1d1e5c90008a1b2c3"));
+        assert_eq!(published.state.as_deref(), Some("ready"));
+    }
+
+    #[test]
+    fn a_deploy_is_read_from_netlifys_json() {
+        let deploy: Deploy = serde_json::from_str(
+            r#"{
+                "id": "6626e3a1d1e5c90008a1b2c3",
+                "site_id": "3970e0fe-8564-4903-9a55-c5f8de49fb8b",
+                "state": "error",
+                "created_at": "2024-04-22T22:30:25.540Z",
+                "title": "Add a post",
+                "draft": true,
+                "required": ["907d14fb3af2b0d4f18c2d46abe8aedce17367bd"],
+                "error_message": "Deploy directory 'public' does not exist",
+                "deploy_ssl_url": "https://6626e3a1d1e5c90008a1b2c3--driftwood-blog.netlify.app",
+                "context": "production"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(deploy.state.as_deref(), Some("error"));
+        assert_eq!(deploy.draft, Some(true));
+        assert_eq!(
+            deploy.required,
+            Some(vec!["907d14fb3af2b0d4f18c2d46abe8aedce17367bd".to_string()])
+        );
+        assert_eq!(
+            deploy.error_message.as_deref(),
+            Some("Deploy directory 'public' does not exist")
+        );
+        assert_eq!(deploy.deploy_url, None);
+
+        // every field is optional
+        let empty: Deploy = serde_json::from_str("{}").unwrap();
+        assert_eq!(empty.id, None);
+    }
+
+    #[test]
+    fn files_certificates_and_users_are_read_from_netlifys_json() {
+        let file: DeployFile = serde_json::from_str(
+            r#"{"id": "/index.html", "path": "/index.html", "sha": "907d14fb", "mime_type": "text/html", "size": 512}"#,
+        )
+        .unwrap();
+        assert_eq!(file.path.as_deref(), Some("/index.html"));
+        assert_eq!(file.size, Some(512));
+
+        let certificate: SslCertificate = serde_json::from_str(
+            r#"{"state": "issued", "domains": ["blog.example.com"], "expires_at": "2024-07-21T00:00:00Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(certificate.state.as_deref(), Some("issued"));
+        assert_eq!(
+            certificate.domains,
+            Some(vec!["blog.example.com".to_string()])
+        );
+
+        let user: User = serde_json::from_str(
+            r#"{"id": "5a1b2c", "email": "jo@example.com", "full_name": "Jo Bloggs", "site_count": 3}"#,
+        )
+        .unwrap();
+        assert_eq!(user.email.as_deref(), Some("jo@example.com"));
+        assert_eq!(user.full_name.as_deref(), Some("Jo Bloggs"));
+    }
+
+    #[test]
+    fn site_settings_only_send_what_is_set() {
+        let settings = SiteSettings {
+            name: Some("new-name".to_string()),
+            custom_domain: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&settings).unwrap(),
+            r#"{"name":"new-name"}"#
+        );
+    }
+
+    #[test]
+    fn site_details_prefer_the_https_url() {
+        let site = Site {
+            id: Some("1".to_string()),
+            name: Some("blog".to_string()),
+            url: Some("http://blog.netlify.app".to_string()),
+            ssl_url: Some("https://blog.netlify.app".to_string()),
+            ssl: Some(true),
+            ..Site::default()
+        };
+        let details = SiteDetails::from(site.clone());
+        assert_eq!(details.url.as_deref(), Some("https://blog.netlify.app"));
+        assert_eq!(details.name.as_deref(), Some("blog"));
+        assert_eq!(details.id.as_deref(), Some("1"));
+        assert_eq!(details.ssl, Some(true));
+
+        for ssl_url in [None, Some(String::new())] {
+            let details = SiteDetails::from(Site {
+                ssl_url,
+                ..site.clone()
+            });
+            assert_eq!(details.url.as_deref(), Some("http://blog.netlify.app"));
+        }
+    }
+}
@@ -0,0 +1,309 @@
+/// Netlify OAuth Module
+/// How the app logs in to Netlify through the browser.
+/// By default the login goes through the Driftwood relay, which holds the OAuth app's secret and
+/// sends the token back encrypted. Setting NETLIFY_CLIENT_ID logs in directly against Netlify with
+/// your own OAuth app instead, using PKCE so the app's secret isn't needed.
+use super::callback::{CallbackServer, DEFAULT_PORT};
+use super::token::StoredToken;
+use driftwood::OAuth2;
+use oauth2::{
+    basic::BasicClient, reqwest::http_client, AuthUrl, AuthorizationCode, ClientId, ClientSecret,
+    PkceCodeChallenge, RedirectUrl, TokenResponse, TokenUrl,
+};
+use reqwest::Url;
+use std::error::Error;
+
+/// The relay used unless DRIFTWOOD_AUTH_HOST is set
+const DEFAULT_RELAY_HOST: &str = "https://auth.driftwoodapp.com";
+/// Netlify's authorization page, unless NETLIFY_AUTH_URL is set
+const DEFAULT_AUTH_URL: &str = "https://app.netlify.com/authorize";
+/// Netlify's token endpoint, unless NETLIFY_TOKEN_URL is set
+const DEFAULT_TOKEN_URL: &str = "https://api.netlify.com/oauth/token";
+
+/// LoginFlow enum
+/// Relay: Log in through the Driftwood relay
+/// Pkce: Log in directly against Netlify with the user's own OAuth app
+#[derive(Debug, Clone)]
+pub enum LoginFlow {
+    Relay(RelayConfig),
+    Pkce(OAuthApp),
+}
+
+impl LoginFlow {
+    /// The PKCE flow when NETLIFY_CLIENT_ID is set, the relay otherwise
+    pub fn from_env() -> LoginFlow {
+        LoginFlow::from_vars(env_var)
+    }
+
+    /// Choose the flow from variables looked up by name
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> LoginFlow {
+        match OAuthApp::from_vars(&var) {
+            Some(app) => LoginFlow::Pkce(app),
+            None => LoginFlow::Relay(RelayConfig::from_vars(&var)),
+        }
+    }
+}
+
+/// RelayConfig struct
+/// Where the relay that logs in on the app's behalf lives
+/// host: The relay's base URL, from DRIFTWOOD_AUTH_HOST
+/// login_path: The page the browser is sent to, from DRIFTWOOD_AUTH_LOGIN_PATH
+/// callback_path: The endpoint the code is exchanged at, from DRIFTWOOD_AUTH_CALLBACK_PATH
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    pub host: String,
+    pub login_path: String,
+    pub callback_path: String,
+}
+
+impl RelayConfig {
+    /// Read the relay's location from the environment, defaulting to the Driftwood relay
+    pub fn from_env() -> RelayConfig {
+        RelayConfig::from_vars(env_var)
+    }
+
+    /// Read the relay's location from variables looked up by name
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> RelayConfig {
+        let var = |name: &str, default: &str| lookup(name).unwrap_or_else(|| default.to_string());
+        RelayConfig {
+            host: var("DRIFTWOOD_AUTH_HOST", DEFAULT_RELAY_HOST),
+            login_path: var("DRIFTWOOD_AUTH_LOGIN_PATH", "/login"),
+            callback_path: var("DRIFTWOOD_AUTH_CALLBACK_PATH", "/callback"),
+        }
+    }
+
+    /// The URL of the relay's login page
+    pub fn login_url(&self) -> String {
+        format!("{}{}", self.host.trim_end_matches('/'), self.login_path)
+    }
+
+    /// The URL the browser is sent to to start a login
+    /// The relay sends the browser back to the redirect URI with the state it was given
+    /// public_key_pem: The key the relay encrypts the token with
+    /// state: The CSRF state the callback has to come back with
+    /// redirect_uri: Where the callback server is listening
+    pub fn authorize_url(&self, public_key_pem: &str, state: &str, redirect_uri: &str) -> String {
+        format!(
+            "{}?public_key_pem={}&state={}&redirect_uri={}",
+            self.login_url(),
+            urlencoding::encode(public_key_pem),
+            urlencoding::encode(state),
+            urlencoding::encode(redirect_uri)
+        )
+    }
+
+    /// The URL the authorization code is exchanged for a token at
+    pub fn callback_url(&self) -> String {
+        format!("{}{}", self.host.trim_end_matches('/'), self.callback_path)
+    }
+}
+
+/// OAuthApp struct
+/// A Netlify OAuth app to log in with directly
+/// client_id: The app's client ID, from NETLIFY_CLIENT_ID
+/// client_secret: The app's secret, from NETLIFY_CLIENT_SECRET, only sent if it's set
+/// auth_url: Netlify's authorization page, from NETLIFY_AUTH_URL
+/// token_url: Netlify's token endpoint, from NETLIFY_TOKEN_URL
+/// redirect_uri: The callback URL registered with the app, from NETLIFY_REDIRECT_URI,
+/// without it the callback server picks one
+#[derive(Debug, Clone)]
+pub struct OAuthApp {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub auth_url: String,
+    pub token_url: String,
+    pub redirect_uri: Option<String>,
+}
+
+impl OAuthApp {
+    /// Read the app from the environment, None if NETLIFY_CLIENT_ID isn't set
+    pub fn from_env() -> Option<OAuthApp> {
+        OAuthApp::from_vars(env_var)
+    }
+
+    /// Read the app from variables looked up by name, None if NETLIFY_CLIENT_ID isn't set
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Option<OAuthApp> {
+        let var = |name: &str| lookup(name).filter(|value| !value.is_empty());
+        Some(OAuthApp {
+            client_id: var("NETLIFY_CLIENT_ID")?,
+            client_secret: var("NETLIFY_CLIENT_SECRET"),
+            auth_url: var("NETLIFY_AUTH_URL").unwrap_or_else(|| DEFAULT_AUTH_URL.to_string()),
+            token_url: var("NETLIFY_TOKEN_URL").unwrap_or_else(|| DEFAULT_TOKEN_URL.to_string()),
+            redirect_uri: var("NETLIFY_REDIRECT_URI"),
+        })
+    }
+
+    /// An OAuth client for the app
+    pub fn client(&self) -> Result<BasicClient, Box<dyn Error>> {
+        Ok(BasicClient::new(
+            ClientId::new(self.client_id.clone()),
+            self.client_secret.clone().map(ClientSecret::new),
+            AuthUrl::new(self.auth_url.clone())?,
+            Some(TokenUrl::new(self.token_url.clone())?),
+        ))
+    }
+
+    /// Log in through the browser with an authorization code and PKCE
+    /// Returns the new token
+    pub fn log_in(&self) -> Result<StoredToken, Box<dyn Error>> {
+        println!("> Logging in with Netlify OAuth app {}...", self.client_id);
+
+        // a registered redirect URI has to be matched exactly, so its port can't be swapped
+        let server = match &self.redirect_uri {
+            Some(redirect_uri) => CallbackServer::bind_redirect_uri(&Url::parse(redirect_uri)?)?,
+            None => CallbackServer::bind(DEFAULT_PORT)?,
+        };
+        let client = self
+            .client()?
+            .set_redirect_uri(RedirectUrl::new(server.redirect_uri().to_string())?);
+
+        let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+        let (auth_url, _) = client
+            .authorize_url(|| server.state().clone())
+            .set_pkce_challenge(challenge)
+            .url();
+        open_browser(auth_url.as_str());
+
+        let callback = server.wait_for_code()?;
+        println!("> Exchanging code for token...");
+        let response = client
+            .exchange_code(AuthorizationCode::new(callback.code))
+            .set_pkce_verifier(verifier)
+            .request(http_client)?;
+
+        Ok(StoredToken::new(
+            response.access_token().secret().to_string(),
+            response.expires_in(),
+            response
+                .refresh_token()
+                .map(|token| token.secret().to_string()),
+        ))
+    }
+}
+
+/// Look a variable up in the environment
+fn env_var(name: &str) -> Option<String> {
+    OAuth2::get_env_var(name).ok()
+}
+
+/// Open a login page in the user's browser, printing it when there's no browser to open
+pub fn open_browser(url: &str) {
+    if let Err(e) = webbrowser::open(url) {
+        println!("> Couldn't open a browser: {}", e);
+        println!("Open this URL in your browser:\n{}\n", url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relay_login_carries_the_state_and_redirect_uri() {
+        let relay = RelayConfig {
+            host: "https://relay.example.com/".to_string(),
+            login_path: "/login".to_string(),
+            callback_path: "/callback".to_string(),
+        };
+        let url = Url::parse(&relay.authorize_url(
+            "-----BEGIN PUBLIC KEY-----\nkey\n",
+            "s+t/a=te",
+            "http://127.0.0.1:8001/callback",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            url.as_str().split('?').next(),
+            Some("https://relay.example.com/login")
+        );
+        let query = url.query_pairs().into_owned().collect::<Vec<_>>();
+        assert_eq!(
+            query,
+            [
+                (
+                    "public_key_pem".to_string(),
+                    "-----BEGIN PUBLIC KEY-----\nkey\n".to_string()
+                ),
+                ("state".to_string(), "s+t/a=te".to_string()),
+                (
+                    "redirect_uri".to_string(),
+                    "http://127.0.0.1:8001/callback".to_string()
+                ),
+            ]
+        );
+    }
+
+    /// Look variables up in a fixed list instead of the environment
+    fn vars<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn logs_in_with_the_users_own_app_when_it_has_a_client_id() {
+        let flow = LoginFlow::from_vars(vars(&[
+            ("NETLIFY_CLIENT_ID", "client"),
+            ("NETLIFY_REDIRECT_URI", "http://localhost:9000/callback"),
+            ("DRIFTWOOD_AUTH_HOST", "https://relay.example.com"),
+        ]));
+        let LoginFlow::Pkce(app) = flow else {
+            panic!("expected the PKCE flow, got {:?}", flow);
+        };
+        assert_eq!(app.client_id, "client");
+        assert_eq!(app.client_secret, None);
+        assert_eq!(app.auth_url, DEFAULT_AUTH_URL);
+        assert_eq!(app.token_url, DEFAULT_TOKEN_URL);
+        assert_eq!(
+            app.redirect_uri.as_deref(),
+            Some("http://localhost:9000/callback")
+        );
+    }
+
+    #[test]
+    fn logs_in_through_the_relay_without_a_client_id() {
+        for vars_set in [&[][..], &[("NETLIFY_CLIENT_ID", "")][..]] {
+            let flow = LoginFlow::from_vars(vars(vars_set));
+            assert!(matches!(flow, LoginFlow::Relay(_)), "{:?}", flow);
+        }
+    }
+
+    #[test]
+    fn the_relay_defaults_to_the_driftwood_relay() {
+        let relay = RelayConfig::from_vars(vars(&[]));
+        assert_eq!(relay.login_url(), "https://auth.driftwoodapp.com/login");
+        assert_eq!(
+            relay.callback_url(),
+            "https://auth.driftwoodapp.com/callback"
+        );
+    }
+
+    #[test]
+    fn the_relay_location_can_be_overridden() {
+        let relay = RelayConfig::from_vars(vars(&[
+            ("DRIFTWOOD_AUTH_HOST", "http://localhost:3000/"),
+            ("DRIFTWOOD_AUTH_LOGIN_PATH", "/auth/start"),
+            ("DRIFTWOOD_AUTH_CALLBACK_PATH", "/auth/token"),
+        ]));
+        assert_eq!(relay.login_url(), "http://localhost:3000/auth/start");
+        assert_eq!(relay.callback_url(), "http://localhost:3000/auth/token");
+    }
+
+    #[test]
+    fn the_app_reads_its_overrides_and_skips_empty_ones() {
+        let app = OAuthApp::from_vars(vars(&[
+            ("NETLIFY_CLIENT_ID", "client"),
+            ("NETLIFY_CLIENT_SECRET", "secret"),
+            ("NETLIFY_AUTH_URL", "http://localhost:3000/authorize"),
+            ("NETLIFY_TOKEN_URL", ""),
+            ("NETLIFY_REDIRECT_URI", ""),
+        ]))
+        .unwrap();
+        assert_eq!(app.client_secret.as_deref(), Some("secret"));
+        assert_eq!(app.auth_url, "http://localhost:3000/authorize");
+        assert_eq!(app.token_url, DEFAULT_TOKEN_URL);
+        assert_eq!(app.redirect_uri, None);
+    }
+}
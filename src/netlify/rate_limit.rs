@@ -0,0 +1,156 @@
+/// Netlify Rate Limit Module
+/// Tracks the API quota Netlify reports in its `X-RateLimit-*` headers,
+/// so the client slows down before it runs out instead of failing partway through a deploy.
+use reqwest::header::HeaderMap;
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Requests left in the window at which the client starts spreading out the rest
+const RESERVE: u64 = 10;
+/// How long to wait after a 429 that didn't say how long to wait
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(10);
+/// The longest a single wait can be, in case Netlify reports a reset far in the future
+const MAX_WAIT: Duration = Duration::from_secs(120);
+
+/// RateLimit struct
+/// The quota as of the last response
+/// limit: Requests allowed per window
+/// remaining: Requests left in the current window
+/// reset: When the current window ends
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimit {
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    pub reset: Option<SystemTime>,
+}
+
+/// RateLimiter struct
+/// Shared by every request the client sends, including the parallel file uploads
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    state: Mutex<RateLimit>,
+}
+
+impl RateLimiter {
+    /// The quota as of the last response
+    pub fn current(&self) -> RateLimit {
+        *self.state.lock().unwrap()
+    }
+
+    /// Record the quota from a response's headers, responses without them are ignored
+    pub fn update(&self, headers: &HeaderMap) {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        let remaining = header("x-ratelimit-remaining");
+        if remaining.is_none() {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.remaining = remaining;
+        state.limit = header("x-ratelimit-limit").or(state.limit);
+        state.reset = header("x-ratelimit-reset")
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+            .or(state.reset);
+    }
+
+    /// Wait before sending a request if the quota is nearly used up, then take one request
+    /// from the quota, so parallel uploads don't all spend the same request
+    /// With nothing left it waits for the window to reset, with a few requests left
+    /// it spreads them out over the rest of the window
+    pub fn throttle(&self) {
+        loop {
+            // the request is counted under the lock, before anyone else can see the same quota
+            let (remaining, wait, reserved) = {
+                let mut state = self.state.lock().unwrap();
+                let (Some(remaining), Some(reset)) = (state.remaining, state.reset) else {
+                    return;
+                };
+                let until_reset = reset
+                    .duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO);
+
+                if remaining == 0 {
+                    if until_reset.is_zero() {
+                        // the window has reset, so the full quota is back until a response says otherwise
+                        state.remaining = state.limit;
+                        continue;
+                    }
+                    (remaining, until_reset, false)
+                } else {
+                    state.remaining = Some(remaining - 1);
+                    if remaining > RESERVE {
+                        return;
+                    }
+                    (remaining, until_reset / (remaining as u32 + 1), true)
+                }
+            };
+
+            let wait = wait.min(MAX_WAIT);
+            if !wait.is_zero() {
+                println!(
+                    "> {} API requests left, waiting {}s before the next one",
+                    remaining,
+                    wait.as_secs_f32().ceil()
+                );
+                thread::sleep(wait);
+            }
+            if reserved {
+                return;
+            }
+        }
+    }
+
+    /// How long to wait after a 429 before trying again
+    /// retry_after: The response's Retry-After header, if it had one
+    pub fn retry_delay(&self, retry_after: Option<Duration>) -> Duration {
+        let until_reset = self
+            .current()
+            .reset
+            .and_then(|reset| reset.duration_since(SystemTime::now()).ok());
+        retry_after
+            .or(until_reset)
+            .unwrap_or(DEFAULT_RETRY_AFTER)
+            .min(MAX_WAIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(remaining: u64, reset: SystemTime) -> RateLimiter {
+        RateLimiter {
+            state: Mutex::new(RateLimit {
+                limit: Some(500),
+                remaining: Some(remaining),
+                reset: Some(reset),
+            }),
+        }
+    }
+
+    #[test]
+    fn parallel_requests_each_take_one_from_the_quota() {
+        let limiter = limiter(100, SystemTime::now() + Duration::from_secs(60));
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| limiter.throttle());
+            }
+        });
+        assert_eq!(limiter.current().remaining, Some(92));
+    }
+
+    #[test]
+    fn an_empty_quota_is_refilled_once_the_window_resets() {
+        let limiter = limiter(0, SystemTime::now() - Duration::from_secs(1));
+        limiter.throttle();
+        assert_eq!(limiter.current().remaining, Some(499));
+    }
+}
@@ -0,0 +1,303 @@
+/// Netlify Token Module
+/// The access token sent with every request, and what's known about it.
+/// It's kept as JSON in the credential store so the client can tell when the token runs out
+/// and, when the login gave one, renew it with the refresh token instead of logging in again.
+use super::oauth::OAuthApp;
+use crate::credentials::CredentialStore;
+use chrono::{DateTime, Utc};
+use oauth2::{reqwest::http_client, RefreshToken, TokenResponse};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, io, path::Path, time::Duration};
+
+/// The key the token is saved under in the credential store
+pub const TOKEN_KEY: &str = "netlify";
+/// Where the token used to be kept, in plain text in the working directory
+pub const LEGACY_TOKEN_FILE: &str = "netlify_token.json";
+/// The environment variable a personal access token can be given in, used instead of the saved token
+pub const AUTH_TOKEN_VAR: &str = "NETLIFY_AUTH_TOKEN";
+/// How long before it runs out a token is treated as expired, so it isn't rejected mid-deploy
+const EXPIRY_MARGIN: chrono::Duration = chrono::Duration::seconds(60);
+
+/// StoredToken struct
+/// The access token and its metadata, as saved in the credential store
+/// access_token: The token sent with every request
+/// issued_at: When the token was issued, in RFC 3339 form
+/// expires_at: When the token runs out, in RFC 3339 form, tokens without one last until revoked
+/// refresh_token: Exchanged for a new access token without logging in again, if the login gave one
+/// personal: Whether it's a personal access token, which is never replaced by logging in through the browser
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredToken {
+    pub access_token: String,
+    pub issued_at: String,
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub personal: bool,
+}
+
+impl StoredToken {
+    /// A token that was just issued
+    /// expires_in: How long the token is valid for, if it runs out at all
+    pub fn new(
+        access_token: String,
+        expires_in: Option<Duration>,
+        refresh_token: Option<String>,
+    ) -> StoredToken {
+        let issued_at = Utc::now();
+        let expires_at = expires_in
+            .and_then(|expires_in| chrono::Duration::from_std(expires_in).ok())
+            .map(|expires_in| (issued_at + expires_in).to_rfc3339());
+        StoredToken {
+            access_token,
+            issued_at: issued_at.to_rfc3339(),
+            expires_at,
+            refresh_token,
+            personal: false,
+        }
+    }
+
+    /// A personal access token created in the Netlify dashboard
+    pub fn personal(access_token: &str) -> StoredToken {
+        StoredToken {
+            personal: true,
+            ..StoredToken::new(access_token.trim().to_string(), None, None)
+        }
+    }
+
+    /// The personal access token in NETLIFY_AUTH_TOKEN, if it's set
+    pub fn from_env() -> Option<StoredToken> {
+        StoredToken::from_var(std::env::var(AUTH_TOKEN_VAR).ok())
+    }
+
+    /// The personal access token in the value of NETLIFY_AUTH_TOKEN, a blank value is no token
+    fn from_var(value: Option<String>) -> Option<StoredToken> {
+        value
+            .filter(|token| !token.trim().is_empty())
+            .map(|token| StoredToken::personal(&token))
+    }
+
+    /// Read the saved token, if there is one
+    /// A token still in the old plain text file is moved into the store and the file removed
+    pub fn load(store: &dyn CredentialStore) -> anyhow::Result<Option<StoredToken>> {
+        StoredToken::load_with_legacy(store, Path::new(LEGACY_TOKEN_FILE))
+    }
+
+    /// Read the saved token, moving one from the old plain text file into the store
+    /// legacy_file: Where the old plain text file would be
+    fn load_with_legacy(
+        store: &dyn CredentialStore,
+        legacy_file: &Path,
+    ) -> anyhow::Result<Option<StoredToken>> {
+        if let Some(saved) = store.get(TOKEN_KEY)? {
+            return Ok(Some(serde_json::from_str(&saved)?));
+        }
+
+        let Some(token) = StoredToken::load_file(legacy_file)? else {
+            return Ok(None);
+        };
+        token.save(store)?;
+        fs::remove_file(legacy_file)?;
+        println!(
+            "> Moved the token from {} to the {}",
+            LEGACY_TOKEN_FILE,
+            store.name()
+        );
+        Ok(Some(token))
+    }
+
+    /// Save the token, replacing the one in the store
+    pub fn save(&self, store: &dyn CredentialStore) -> anyhow::Result<()> {
+        store.set(TOKEN_KEY, &serde_json::to_string(self)?)
+    }
+
+    /// Remove the saved token, including one still in the old plain text file
+    pub fn delete(store: &dyn CredentialStore) -> anyhow::Result<()> {
+        store.delete(TOKEN_KEY)?;
+        let legacy_file = Path::new(LEGACY_TOKEN_FILE);
+        if legacy_file.exists() {
+            fs::remove_file(legacy_file)?;
+        }
+        Ok(())
+    }
+
+    /// Read a plain text token file, if there is one
+    /// Files from before the metadata was kept hold only the token, they're read as a token
+    /// issued when the file was written that never expires
+    fn load_file(path: &Path) -> io::Result<Option<StoredToken>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)?;
+        if let Ok(token) = serde_json::from_str(&contents) {
+            return Ok(Some(token));
+        }
+
+        let issued_at: DateTime<Utc> = fs::metadata(path)?
+            .modified()
+            .map(DateTime::from)
+            .unwrap_or_else(|_| Utc::now());
+        Ok(Some(StoredToken {
+            access_token: contents.trim().to_string(),
+            issued_at: issued_at.to_rfc3339(),
+            expires_at: None,
+            refresh_token: None,
+            personal: false,
+        }))
+    }
+
+    /// Whether the token has run out, or is about to
+    /// Tokens with no expiry, or one that can't be read, are only found out by a 401
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(Utc::now())
+    }
+
+    /// Whether the token has run out, or is about to, at the given time
+    fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at
+            .as_deref()
+            .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+            .is_some_and(|expires_at| expires_at <= now + EXPIRY_MARGIN)
+    }
+
+    /// Exchange the refresh token for a new access token
+    /// Needs NETLIFY_CLIENT_ID, the OAuth app the token was issued to
+    /// Returns the new token, keeping the old refresh token if Netlify didn't send a new one
+    pub fn refresh(&self) -> Result<StoredToken, Box<dyn Error>> {
+        let refresh_token = self
+            .refresh_token
+            .clone()
+            .ok_or("The token has no refresh token")?;
+        let app = OAuthApp::from_env().ok_or("Refreshing the token needs NETLIFY_CLIENT_ID")?;
+        println!("> Refreshing the Netlify token...");
+
+        let response = app
+            .client()?
+            .exchange_refresh_token(&RefreshToken::new(refresh_token.clone()))
+            .request(http_client)?;
+
+        Ok(StoredToken::new(
+            response.access_token().secret().to_string(),
+            response.expires_in(),
+            response
+                .refresh_token()
+                .map(|token| token.secret().to_string())
+                .or(Some(refresh_token)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, sync::Mutex};
+
+    /// A credential store that only keeps secrets in memory
+    #[derive(Default)]
+    struct MemoryStore(Mutex<HashMap<String, String>>);
+
+    impl CredentialStore for MemoryStore {
+        fn name(&self) -> &str {
+            "memory"
+        }
+
+        fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        fn set(&self, key: &str, secret: &str) -> anyhow::Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), secret.to_string());
+            Ok(())
+        }
+
+        fn delete(&self, key: &str) -> anyhow::Result<()> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tokens_expire_a_minute_early() {
+        let now = Utc::now();
+        let expiring_in = |seconds| StoredToken {
+            expires_at: Some((now + chrono::Duration::seconds(seconds)).to_rfc3339()),
+            ..StoredToken::new("token".to_string(), None, None)
+        };
+
+        assert!(!expiring_in(120).is_expired_at(now));
+        assert!(!expiring_in(61).is_expired_at(now));
+        assert!(expiring_in(60).is_expired_at(now));
+        assert!(expiring_in(-10).is_expired_at(now));
+
+        let never = StoredToken::new("token".to_string(), None, None);
+        assert!(!never.is_expired_at(now));
+        let unreadable = StoredToken {
+            expires_at: Some("soon".to_string()),
+            ..never
+        };
+        assert!(!unreadable.is_expired_at(now));
+    }
+
+    #[test]
+    fn personal_tokens_are_trimmed_and_never_expire() {
+        let token = StoredToken::personal("  nfp_abc\n");
+        assert_eq!(token.access_token, "nfp_abc");
+        assert!(token.personal);
+        assert_eq!(token.expires_at, None);
+        assert_eq!(token.refresh_token, None);
+    }
+
+    #[test]
+    fn only_a_non_blank_env_value_is_a_token() {
+        assert!(StoredToken::from_var(None).is_none());
+        assert!(StoredToken::from_var(Some(" \n".to_string())).is_none());
+
+        let token = StoredToken::from_var(Some("nfp_abc ".to_string())).unwrap();
+        assert_eq!(token.access_token, "nfp_abc");
+        assert!(token.personal);
+    }
+
+    #[test]
+    fn a_legacy_token_file_is_moved_into_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_file = dir.path().join(LEGACY_TOKEN_FILE);
+        let store = MemoryStore::default();
+        assert!(StoredToken::load_with_legacy(&store, &legacy_file)
+            .unwrap()
+            .is_none());
+
+        fs::write(&legacy_file, "old-token\n").unwrap();
+        let token = StoredToken::load_with_legacy(&store, &legacy_file)
+            .unwrap()
+            .unwrap();
+        assert_eq!(token.access_token, "old-token");
+        assert!(!token.personal);
+        assert!(!legacy_file.exists());
+
+        let saved: StoredToken =
+            serde_json::from_str(&store.get(TOKEN_KEY).unwrap().unwrap()).unwrap();
+        assert_eq!(saved.access_token, "old-token");
+    }
+
+    #[test]
+    fn the_saved_token_wins_over_a_legacy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_file = dir.path().join(LEGACY_TOKEN_FILE);
+        let store = MemoryStore::default();
+        StoredToken::new("saved".to_string(), None, None)
+            .save(&store)
+            .unwrap();
+        let legacy = StoredToken::new("legacy".to_string(), None, None);
+        fs::write(&legacy_file, serde_json::to_string(&legacy).unwrap()).unwrap();
+
+        let token = StoredToken::load_with_legacy(&store, &legacy_file)
+            .unwrap()
+            .unwrap();
+        assert_eq!(token.access_token, "saved");
+        assert!(legacy_file.exists());
+    }
+}
@@ -0,0 +1,410 @@
+/// SFTP Module
+/// A hosting provider that uploads the built site to a plain web server over SFTP.
+/// A manifest of SHA1 hashes is kept on the server, so only changed files are uploaded
+/// and files that are gone from the build are pruned.
+use crate::config::{SftpConfig, SiteConfig};
+use crate::hosting::{
+    list_local_sites, new_local_site, rename_local_site, Deployment, HostingProvider,
+};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::SiteDetails;
+use anyhow::{anyhow, bail, Context, Result};
+use ssh2::{CheckResult, ErrorCode, KnownHostFileKind, Session, Sftp};
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    io::{Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+};
+
+/// SFTP status code for a file that doesn't exist
+const NO_SUCH_FILE: i32 = 2;
+
+/// SftpTarget struct
+/// config: Overrides the `[sftp]` section of the site's config file
+#[derive(Default)]
+pub struct SftpTarget {
+    config: Option<SftpConfig>,
+}
+
+impl SftpTarget {
+    /// Create a target that reads its settings from each site's config file
+    pub fn new() -> SftpTarget {
+        SftpTarget { config: None }
+    }
+
+    /// Create a target with the given settings, ignoring the site's config file
+    pub fn with_config(config: SftpConfig) -> SftpTarget {
+        SftpTarget {
+            config: Some(config),
+        }
+    }
+
+    /// Upload a built site, skipping files the server already has
+    /// output_path: The directory the site was built into
+    /// Returns the manifest now stored on the server
+    pub fn publish(&self, output_path: &Path, config: &SftpConfig) -> Result<Manifest> {
+        println!(
+            ">> Uploading {} to {}:{}",
+            output_path.display(),
+            config.host,
+            config.remote_path
+        );
+
+        if !output_path.join("index.html").exists() {
+            bail!("index.html not found in {}", output_path.display());
+        }
+
+        let remote = RemoteSite::connect(config)?;
+        let local = Manifest::build(output_path)?;
+        let diff = local.diff(&remote.read_manifest()?);
+
+        for path in &diff.changed {
+            println!("> Uploading: {}", path);
+            let contents = fs::read(Manifest::local_path(output_path, path))
+                .with_context(|| format!("Failed to read {}", path))?;
+            remote.upload(path, &contents)?;
+        }
+
+        for path in &diff.removed {
+            println!("> Deleting: {}", path);
+            remote.remove(path)?;
+        }
+        remote.prune_dirs(&diff.removed);
+
+        // written last, so an interrupted deploy is retried on the next one
+        remote.upload(&format!("/{}", MANIFEST_FILE), local.to_json()?.as_bytes())?;
+
+        println!(
+            ">> Uploaded {} files, deleted {}, {} unchanged",
+            diff.changed.len(),
+            diff.removed.len(),
+            local.files.len() - diff.changed.len()
+        );
+        Ok(local)
+    }
+
+    /// The settings for a site, from the override or the site's config file
+    fn config_for(&self, site: &SiteDetails) -> Result<SftpConfig> {
+        if let Some(config) = &self.config {
+            return Ok(config.clone());
+        }
+        SiteConfig::load(site)?
+            .sftp
+            .ok_or_else(|| anyhow!("Add an [sftp] section to the site's driftwood.toml"))
+    }
+}
+
+impl HostingProvider for SftpTarget {
+    fn name(&self) -> &str {
+        "SFTP"
+    }
+
+    fn create_site(&self, name: &str) -> Result<SiteDetails> {
+        Ok(new_local_site(name))
+    }
+
+    fn list_sites(&self) -> Result<Vec<SiteDetails>> {
+        list_local_sites()
+    }
+
+    fn update_site(&self, site: &SiteDetails, new_details: SiteDetails) -> Result<SiteDetails> {
+        rename_local_site(site, new_details)
+    }
+
+    /// Deletes every file the last deploy uploaded, other files on the server are kept
+    fn delete_site(&self, site: &SiteDetails) -> Result<()> {
+        let config = self.config_for(site)?;
+        let remote = RemoteSite::connect(&config)?;
+        let paths: Vec<String> = remote.read_manifest()?.files.into_keys().collect();
+        for path in &paths {
+            remote.remove(path)?;
+        }
+        remote.prune_dirs(&paths);
+        remote.remove(&format!("/{}", MANIFEST_FILE))
+    }
+
+    fn deploy(&self, site: &SiteDetails, output_path: &Path) -> Result<Deployment> {
+        let config = self.config_for(site)?;
+        self.publish(output_path, &config)?;
+        Ok(Deployment {
+            id: None,
+            url: SiteConfig::load(site)?.resolve_base_url(site),
+        })
+    }
+}
+
+/// RemoteSite struct
+/// An SFTP connection to the directory a site is served from
+/// The session is kept so the connection stays open
+struct RemoteSite {
+    _session: Session,
+    sftp: Sftp,
+    root: PathBuf,
+}
+
+impl RemoteSite {
+    /// Connect, check the server's key against known_hosts and log in
+    fn connect(config: &SftpConfig) -> Result<RemoteSite> {
+        let tcp = TcpStream::connect((config.host.as_str(), config.port))
+            .with_context(|| format!("Failed to connect to {}:{}", config.host, config.port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake().context("SSH handshake failed")?;
+
+        check_host_key(&session, config)?;
+
+        if let Some(private_key) = &config.private_key {
+            let passphrase = env::var("DRIFTWOOD_SFTP_KEY_PASSPHRASE").ok();
+            session
+                .userauth_pubkey_file(
+                    &config.username,
+                    None,
+                    Path::new(private_key),
+                    passphrase.as_deref(),
+                )
+                .context("Failed to log in with the private key")?;
+        } else if let Ok(password) = env::var("DRIFTWOOD_SFTP_PASSWORD") {
+            session
+                .userauth_password(&config.username, &password)
+                .context("Failed to log in with the password")?;
+        } else {
+            session
+                .userauth_agent(&config.username)
+                .context("Failed to log in with the SSH agent")?;
+        }
+        if !session.authenticated() {
+            bail!("Failed to log in to {} as {}", config.host, config.username);
+        }
+
+        let sftp = session.sftp().context("Failed to start SFTP")?;
+        Ok(RemoteSite {
+            _session: session,
+            sftp,
+            root: PathBuf::from(&config.remote_path),
+        })
+    }
+
+    /// The path on the server of a file in `/path` form
+    fn remote_path(&self, path: &str) -> PathBuf {
+        remote_path(&self.root, path)
+    }
+
+    /// The manifest of the last deploy, empty if there hasn't been one
+    fn read_manifest(&self) -> Result<Manifest> {
+        let mut file = match self.sftp.open(&self.remote_path(MANIFEST_FILE)) {
+            Ok(file) => file,
+            Err(e) if is_missing(&e) => return Ok(Manifest::default()),
+            Err(e) => return Err(e).context("Failed to open the remote manifest"),
+        };
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .context("Failed to read the remote manifest")?;
+        Manifest::from_json(&contents)
+    }
+
+    /// Write a file, creating the directories it's in
+    fn upload(&self, path: &str, contents: &[u8]) -> Result<()> {
+        let remote_path = self.remote_path(path);
+        if let Some(parent) = remote_path.parent() {
+            self.create_dirs(parent)?;
+        }
+        let mut file = self
+            .sftp
+            .create(&remote_path)
+            .with_context(|| format!("Failed to create {}", remote_path.display()))?;
+        file.write_all(contents)
+            .with_context(|| format!("Failed to write {}", remote_path.display()))?;
+        Ok(())
+    }
+
+    /// Delete a file, a file that's already gone is fine
+    fn remove(&self, path: &str) -> Result<()> {
+        let remote_path = self.remote_path(path);
+        match self.sftp.unlink(&remote_path) {
+            Err(e) if !is_missing(&e) => {
+                Err(e).with_context(|| format!("Failed to delete {}", remote_path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Create a directory and any missing parents
+    fn create_dirs(&self, dir: &Path) -> Result<()> {
+        for dir in missing_dirs(dir, |dir| self.sftp.stat(dir).is_ok()) {
+            self.sftp
+                .mkdir(dir, 0o755)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Remove the directories left empty by deleted files, deepest first
+    /// Directories that still hold files are left alone
+    fn prune_dirs(&self, removed: &[String]) {
+        for dir in dirs_to_prune(removed) {
+            // fails when the directory isn't empty, which is expected
+            let _ = self.sftp.rmdir(&self.root.join(dir));
+        }
+    }
+}
+
+/// The path on the server of a file in `/path` form
+/// root: The directory the site is served from
+fn remote_path(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
+
+/// The directories that have to be made for a directory to exist, outermost first
+/// exists: Whether a directory is already on the server
+fn missing_dirs(dir: &Path, exists: impl Fn(&Path) -> bool) -> Vec<&Path> {
+    let mut missing = Vec::new();
+    let mut current = Some(dir);
+    while let Some(dir) = current {
+        if dir.as_os_str().is_empty() || exists(dir) {
+            break;
+        }
+        missing.push(dir);
+        current = dir.parent();
+    }
+    missing.reverse();
+    missing
+}
+
+/// Every directory below the site's root that held a removed file, deepest first
+/// removed: The removed files in `/path` form
+fn dirs_to_prune(removed: &[String]) -> Vec<PathBuf> {
+    let mut dirs = BTreeSet::new();
+    for path in removed {
+        let mut dir = Path::new(path.trim_start_matches('/')).parent();
+        while let Some(current) = dir.filter(|d| !d.as_os_str().is_empty()) {
+            dirs.insert(current.to_path_buf());
+            dir = current.parent();
+        }
+    }
+    dirs.into_iter().rev().collect()
+}
+
+/// Check the server's key against the known_hosts file
+fn check_host_key(session: &Session, config: &SftpConfig) -> Result<()> {
+    let known_hosts_path = match &config.known_hosts {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(env::var("HOME").context("HOME is not set")?)
+            .join(".ssh")
+            .join("known_hosts"),
+    };
+
+    let mut known_hosts = session.known_hosts()?;
+    if known_hosts_path.exists() {
+        known_hosts
+            .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+            .with_context(|| format!("Failed to read {}", known_hosts_path.display()))?;
+    }
+
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| anyhow!("The server did not send a host key"))?;
+    match known_hosts.check_port(&config.host, config.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => Err(anyhow!(
+            "{} is not in {}, connect with ssh once or add it with ssh-keyscan",
+            config.host,
+            known_hosts_path.display()
+        )),
+        CheckResult::Mismatch => Err(anyhow!(
+            "The host key of {} does not match {}, refusing to connect",
+            config.host,
+            known_hosts_path.display()
+        )),
+        CheckResult::Failure => Err(anyhow!("Failed to check the host key of {}", config.host)),
+    }
+}
+
+fn is_missing(e: &ssh2::Error) -> bool {
+    e.code() == ErrorCode::SFTP(NO_SUCH_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_placed_below_the_remote_root() {
+        let root = Path::new("/var/www/blog");
+        assert_eq!(
+            remote_path(root, "/posts/a/index.html"),
+            Path::new("/var/www/blog/posts/a/index.html")
+        );
+        assert_eq!(remote_path(root, MANIFEST_FILE), root.join(MANIFEST_FILE));
+    }
+
+    #[test]
+    fn only_missing_directories_are_created_outermost_first() {
+        let existing = [Path::new("/var/www/blog")];
+        let exists = |dir: &Path| existing.contains(&dir);
+
+        assert_eq!(
+            missing_dirs(Path::new("/var/www/blog/posts/a"), exists),
+            [
+                Path::new("/var/www/blog/posts"),
+                Path::new("/var/www/blog/posts/a")
+            ]
+        );
+        assert!(missing_dirs(Path::new("/var/www/blog"), exists).is_empty());
+        assert_eq!(
+            missing_dirs(Path::new("public/posts"), |_| false),
+            [Path::new("public"), Path::new("public/posts")]
+        );
+    }
+
+    #[test]
+    fn directories_of_removed_files_are_pruned_deepest_first() {
+        let removed = [
+            "/posts/a/index.html",
+            "/posts/a/images/cat.png",
+            "/posts/b/index.html",
+            "/old.html",
+        ]
+        .map(String::from);
+        assert_eq!(
+            dirs_to_prune(&removed),
+            [
+                PathBuf::from("posts/b"),
+                PathBuf::from("posts/a/images"),
+                PathBuf::from("posts/a"),
+                PathBuf::from("posts"),
+            ]
+        );
+        assert!(dirs_to_prune(&["/index.html".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn the_remote_manifest_decides_what_is_uploaded_and_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("posts/new")).unwrap();
+        fs::write(dir.path().join("index.html"), "new index").unwrap();
+        fs::write(dir.path().join("style.css"), "body {}").unwrap();
+        fs::write(dir.path().join("posts/new/index.html"), "new post").unwrap();
+        let local = Manifest::build(dir.path()).unwrap();
+
+        let mut remote = local.clone();
+        remote.files.remove("/posts/new/index.html");
+        remote
+            .files
+            .insert("/index.html".to_string(), "stale".to_string());
+        remote
+            .files
+            .insert("/posts/old/index.html".to_string(), "gone".to_string());
+        // the manifest is read back from the server as JSON
+        let remote = Manifest::from_json(remote.to_json().unwrap().as_bytes()).unwrap();
+
+        let diff = local.diff(&remote);
+        assert_eq!(diff.changed, ["/index.html", "/posts/new/index.html"]);
+        assert_eq!(diff.removed, ["/posts/old/index.html"]);
+        assert_eq!(
+            dirs_to_prune(&diff.removed),
+            [PathBuf::from("posts/old"), PathBuf::from("posts")]
+        );
+    }
+}
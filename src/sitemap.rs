@@ -0,0 +1,104 @@
+/// Sitemap Module
+/// Writes `sitemap.xml` and `robots.txt` so search engines can find every page of a site.
+use crate::feeds::{escape_xml, to_local};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use std::{fs, path::Path};
+
+pub const SITEMAP_FILE: &str = "sitemap.xml";
+pub const ROBOTS_FILE: &str = "robots.txt";
+
+/// SitemapEntry struct
+/// A single page in the sitemap
+/// path: The path of the page from the site root, e.g. `/posts/hello.html`
+/// lastmod: When the page last changed, if known
+pub(crate) struct SitemapEntry {
+    pub path: String,
+    pub lastmod: Option<NaiveDateTime>,
+}
+
+/// Write `sitemap.xml` listing every entry, and a `robots.txt` pointing to it
+/// base_url: The public URL of the site, without a trailing slash
+pub(crate) fn write_sitemap(
+    base_url: &str,
+    entries: &[SitemapEntry],
+    out_path: &Path,
+) -> Result<()> {
+    println!(">> Writing sitemap");
+    let mut urls = String::new();
+    for entry in entries {
+        urls.push_str("  <url>\n");
+        urls.push_str(&format!(
+            "    <loc>{}{}</loc>\n",
+            escape_xml(base_url),
+            escape_xml(&entry.path)
+        ));
+        if let Some(lastmod) = entry.lastmod.and_then(to_local) {
+            urls.push_str(&format!("    <lastmod>{}</lastmod>\n", lastmod.to_rfc3339()));
+        }
+        urls.push_str("  </url>\n");
+    }
+
+    let sitemap = format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+            "{}",
+            "</urlset>\n"
+        ),
+        urls
+    );
+    fs::write(out_path.join(SITEMAP_FILE), sitemap).context("Failed to write the sitemap")?;
+
+    let robots = format!(
+        "User-agent: *\nAllow: /\n\nSitemap: {}/{}\n",
+        base_url, SITEMAP_FILE
+    );
+    fs::write(out_path.join(ROBOTS_FILE), robots).context("Failed to write robots.txt")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> NaiveDateTime {
+        crate::front_matter::parse_date(date).unwrap()
+    }
+
+    #[test]
+    fn sitemap_lists_absolute_urls_with_their_lastmod() {
+        let out = tempfile::tempdir().unwrap();
+        let entries = [
+            SitemapEntry {
+                path: "/".to_string(),
+                lastmod: Some(date("2024-05-01 09:30:00")),
+            },
+            SitemapEntry {
+                path: "/posts/fish&chips.html".to_string(),
+                lastmod: None,
+            },
+        ];
+        write_sitemap("https://blog.example.com", &entries, out.path()).unwrap();
+
+        let sitemap = fs::read_to_string(out.path().join(SITEMAP_FILE)).unwrap();
+        assert!(sitemap.contains("<loc>https://blog.example.com/</loc>"));
+        assert!(sitemap.contains(&format!(
+            "<lastmod>{}</lastmod>",
+            to_local(date("2024-05-01 09:30:00")).unwrap().to_rfc3339()
+        )));
+        assert!(sitemap.contains("<loc>https://blog.example.com/posts/fish&amp;chips.html</loc>"));
+        assert_eq!(sitemap.matches("<url>").count(), 2);
+        assert_eq!(sitemap.matches("<lastmod>").count(), 1);
+    }
+
+    #[test]
+    fn robots_txt_points_at_the_sitemap() {
+        let out = tempfile::tempdir().unwrap();
+        write_sitemap("https://blog.example.com", &[], out.path()).unwrap();
+
+        let robots = fs::read_to_string(out.path().join(ROBOTS_FILE)).unwrap();
+        assert!(robots.contains("Sitemap: https://blog.example.com/sitemap.xml\n"));
+    }
+}
@@ -0,0 +1,119 @@
+/// Tags Module
+/// Groups posts by tag for the `tags/<tag>/` pages and the `tags/` overview.
+use crate::build::{self, SourcePost};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Tag struct
+/// A tag and every post that has it
+/// name: The tag as first written in a post
+/// slug: The normalised tag, used in URLs
+/// posts: The posts with this tag, newest first
+pub(crate) struct Tag<'a> {
+    pub name: String,
+    pub slug: String,
+    pub posts: Vec<&'a SourcePost>,
+}
+
+/// TagLink struct
+/// A tag as shown on blog cards and post pages
+/// name: The tag as written in the post
+/// url: The link to the tag's page, relative to the page it's shown on
+#[derive(Serialize, Clone)]
+pub(crate) struct TagLink {
+    pub name: String,
+    pub url: String,
+}
+
+/// Normalise a tag for use in URLs
+/// Lowercases it, and turns every run of other characters into a single dash
+pub fn slugify_tag(tag: &str) -> String {
+    tag.trim()
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The path of a tag's page from the site root
+pub(crate) fn tag_url(slug: &str) -> String {
+    format!("/tags/{}/", slug)
+}
+
+/// Build the links for a post's tags
+/// Tags that normalise to nothing or to a tag already listed are skipped
+/// root: The prefix that leads from the page the links are on back to the site root
+pub(crate) fn tag_links(tags: &[String], root: &str) -> Vec<TagLink> {
+    let mut slugs: Vec<String> = Vec::new();
+    let mut links = Vec::new();
+    for tag in tags {
+        let slug = slugify_tag(tag);
+        if slug.is_empty() || slugs.contains(&slug) {
+            continue;
+        }
+        links.push(TagLink {
+            name: tag.trim().to_string(),
+            url: build::relative_url(root, &tag_url(&slug)),
+        });
+        slugs.push(slug);
+    }
+    links
+}
+
+/// Group posts by their normalised tags
+/// posts: The posts, newest first, the order is kept within each tag
+/// Returns the tags sorted by slug
+pub(crate) fn collect_tags(posts: &[SourcePost]) -> Vec<Tag<'_>> {
+    let mut tags: BTreeMap<String, Tag> = BTreeMap::new();
+
+    for post in posts {
+        for name in &post.front_matter.tags {
+            let slug = slugify_tag(name);
+            if slug.is_empty() {
+                continue;
+            }
+            let tag = tags.entry(slug.clone()).or_insert_with(|| Tag {
+                name: name.trim().to_string(),
+                slug,
+                posts: Vec::new(),
+            });
+            // a post listing the same tag twice only shows up once
+            if !tag.posts.iter().any(|p| std::ptr::eq(*p, post)) {
+                tag.posts.push(post);
+            }
+        }
+    }
+
+    tags.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugify_normalises_tags_for_urls() {
+        assert_eq!(slugify_tag("Rust"), "rust");
+        assert_eq!(slugify_tag("  Web Dev  "), "web-dev");
+        assert_eq!(slugify_tag("C++ / systems!"), "c-systems");
+        assert_eq!(slugify_tag("Café"), "café");
+        assert_eq!(slugify_tag("../.."), "");
+    }
+
+    #[test]
+    fn tag_links_skip_empty_and_repeated_tags() {
+        let tags = ["Rust", "rust ", "!!", "Web Dev"].map(String::from);
+        let links = tag_links(&tags, "../");
+        assert_eq!(
+            links
+                .iter()
+                .map(|link| (link.name.as_str(), link.url.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("Rust", "../tags/rust/index.html"),
+                ("Web Dev", "../tags/web-dev/index.html")
+            ]
+        );
+    }
+}
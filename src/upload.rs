@@ -0,0 +1,349 @@
+/// Upload Module
+/// Uploads many files with a bounded number of workers, retrying failures that are worth
+/// retrying with exponential backoff, and reports which files never made it.
+use anyhow::{bail, Result};
+use std::{collections::VecDeque, fmt, sync::Mutex, thread, time::Duration};
+
+/// UploadError enum
+/// Retryable: Server errors, rate limits and network errors, with the delay the server asked for
+/// Fatal: Anything that won't get better by trying again, such as a missing file or a 4xx
+#[derive(Debug, Clone)]
+pub enum UploadError {
+    Retryable {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    Fatal(String),
+}
+
+impl UploadError {
+    /// Classify an unsuccessful HTTP response
+    /// 429 and 5xx responses are retried, everything else is fatal
+    pub fn from_status(status: u16, body: &str, retry_after: Option<Duration>) -> UploadError {
+        let message = format!("Request failed: {} {}", status, body);
+        if status == 429 || status >= 500 {
+            UploadError::Retryable {
+                message,
+                retry_after,
+            }
+        } else {
+            UploadError::Fatal(message)
+        }
+    }
+
+    /// A network error, these are always retried
+    pub fn network(error: impl fmt::Display) -> UploadError {
+        UploadError::Retryable {
+            message: format!("Network error: {}", error),
+            retry_after: None,
+        }
+    }
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::Retryable { message, .. } => write!(f, "{}", message),
+            UploadError::Fatal(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// UploadOptions struct
+/// concurrency: The number of files uploaded at the same time
+/// max_retries: How many times a file is retried before it counts as failed
+/// initial_backoff: The delay before the first retry, doubled for every retry after it
+/// max_backoff: The longest delay between retries
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    pub concurrency: usize,
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions {
+            concurrency: 4,
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl UploadOptions {
+    /// The delay before a retry, attempt 1 being the first retry
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+/// UploadEvent enum
+/// What just happened to a file, passed to the progress callback
+pub enum UploadEvent<'a> {
+    Started {
+        path: &'a str,
+    },
+    Retrying {
+        path: &'a str,
+        attempt: u32,
+        delay: Duration,
+        error: &'a UploadError,
+    },
+    Uploaded {
+        path: &'a str,
+    },
+    Failed {
+        path: &'a str,
+        error: &'a UploadError,
+    },
+}
+
+/// UploadProgress struct
+/// The state of the whole upload, passed to the progress callback
+/// uploaded: Files that have been uploaded
+/// failed: Files that gave up
+/// total: Files to upload
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UploadProgress {
+    pub uploaded: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+/// UploadReport struct
+/// The outcome of an upload
+/// uploaded: The paths that were uploaded
+/// failed: The paths that failed, with the last error for each
+#[derive(Debug, Default)]
+pub struct UploadReport {
+    pub uploaded: Vec<String>,
+    pub failed: Vec<(String, UploadError)>,
+}
+
+impl UploadReport {
+    /// Whether every file was uploaded
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// An error listing every failed file, if there were any
+    pub fn into_result(self) -> Result<UploadReport> {
+        if self.is_success() {
+            return Ok(self);
+        }
+        let failures = self
+            .failed
+            .iter()
+            .map(|(path, error)| format!("  {}: {}", path, error))
+            .collect::<Vec<_>>()
+            .join("\n");
+        bail!(
+            "{} file(s) failed to upload:\n{}",
+            self.failed.len(),
+            failures
+        )
+    }
+}
+
+/// Upload every path, a few at a time
+/// upload: Uploads a single file
+/// progress: Called whenever a file starts, is retried, finishes or fails
+pub fn upload_all<U, P>(
+    paths: &[String],
+    options: &UploadOptions,
+    upload: U,
+    progress: P,
+) -> UploadReport
+where
+    U: Fn(&str) -> Result<(), UploadError> + Sync,
+    P: Fn(&UploadEvent, UploadProgress) + Sync,
+{
+    let queue = Mutex::new(paths.iter().collect::<VecDeque<_>>());
+    let state = Mutex::new((
+        UploadReport::default(),
+        UploadProgress {
+            total: paths.len(),
+            ..Default::default()
+        },
+    ));
+    let current = || state.lock().unwrap().1;
+
+    let worker = || loop {
+        let Some(path) = queue.lock().unwrap().pop_front() else {
+            break;
+        };
+        progress(&UploadEvent::Started { path }, current());
+
+        let mut attempt = 0;
+        let result = loop {
+            match upload(path) {
+                Ok(()) => break Ok(()),
+                Err(error @ UploadError::Retryable { .. }) if attempt < options.max_retries => {
+                    attempt += 1;
+                    let delay = match &error {
+                        UploadError::Retryable {
+                            retry_after: Some(retry_after),
+                            ..
+                        } => *retry_after,
+                        _ => options.backoff(attempt),
+                    };
+                    progress(
+                        &UploadEvent::Retrying {
+                            path,
+                            attempt,
+                            delay,
+                            error: &error,
+                        },
+                        current(),
+                    );
+                    thread::sleep(delay);
+                }
+                Err(error) => break Err(error),
+            }
+        };
+
+        let mut state = state.lock().unwrap();
+        match result {
+            Ok(()) => {
+                state.0.uploaded.push(path.clone());
+                state.1.uploaded += 1;
+                let snapshot = state.1;
+                drop(state);
+                progress(&UploadEvent::Uploaded { path }, snapshot);
+            }
+            Err(error) => {
+                state.1.failed += 1;
+                let snapshot = state.1;
+                state.0.failed.push((path.clone(), error.clone()));
+                drop(state);
+                progress(
+                    &UploadEvent::Failed {
+                        path,
+                        error: &error,
+                    },
+                    snapshot,
+                );
+            }
+        }
+    };
+
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency.clamp(1, paths.len().max(1)) {
+            scope.spawn(worker);
+        }
+    });
+
+    state.into_inner().unwrap().0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn options() -> UploadOptions {
+        UploadOptions {
+            concurrency: 3,
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    fn paths(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn retries_until_the_upload_succeeds() {
+        let attempts = Mutex::new(HashMap::new());
+        let report = upload_all(
+            &paths(&["/a.html", "/b.html"]),
+            &options(),
+            |path| {
+                let mut attempts = attempts.lock().unwrap();
+                let attempt = attempts.entry(path.to_string()).or_insert(0);
+                *attempt += 1;
+                // the first attempt at /a.html hits a server error
+                if path == "/a.html" && *attempt == 1 {
+                    return Err(UploadError::from_status(503, "busy", None));
+                }
+                Ok(())
+            },
+            |_, _| {},
+        );
+
+        assert!(report.is_success());
+        assert_eq!(report.uploaded.len(), 2);
+        assert_eq!(attempts.lock().unwrap()["/a.html"], 2);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry() {
+        let attempts = Mutex::new(0);
+        let retries = Mutex::new(Vec::new());
+        let report = upload_all(
+            &paths(&["/a.html"]),
+            &options(),
+            |_| {
+                *attempts.lock().unwrap() += 1;
+                Err(UploadError::network("connection reset"))
+            },
+            |event, _| {
+                if let UploadEvent::Retrying { attempt, delay, .. } = event {
+                    retries.lock().unwrap().push((*attempt, *delay));
+                }
+            },
+        );
+
+        assert_eq!(*attempts.lock().unwrap(), 3);
+        assert_eq!(
+            *retries.lock().unwrap(),
+            [(1, Duration::from_millis(1)), (2, Duration::from_millis(2))]
+        );
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.into_result().is_err());
+    }
+
+    #[test]
+    fn fatal_errors_are_not_retried() {
+        let attempts = Mutex::new(0);
+        let report = upload_all(
+            &paths(&["/a.html", "/b.html"]),
+            &options(),
+            |path| {
+                if path == "/b.html" {
+                    *attempts.lock().unwrap() += 1;
+                    return Err(UploadError::from_status(422, "bad file", None));
+                }
+                Ok(())
+            },
+            |_, _| {},
+        );
+
+        assert_eq!(*attempts.lock().unwrap(), 1);
+        assert_eq!(report.uploaded, ["/a.html"]);
+        assert_eq!(report.failed[0].0, "/b.html");
+    }
+
+    #[test]
+    fn only_rate_limits_and_server_errors_are_retryable() {
+        let retryable = |status| {
+            matches!(
+                UploadError::from_status(status, "", None),
+                UploadError::Retryable { .. }
+            )
+        };
+        assert!(retryable(429));
+        assert!(retryable(500));
+        assert!(retryable(503));
+        assert!(!retryable(400));
+        assert!(!retryable(404));
+        assert!(!retryable(422));
+    }
+}